@@ -0,0 +1,316 @@
+//! Antenna delay calibration
+//!
+//! The timestamps of the DW1000 are corrected by the antenna delays configured
+//! using [`DW1000::set_antenna_delay`]. If those don't match the actual delays
+//! of a device, every distance it measures is off by a constant amount. This
+//! module determines the correct antenna delays from range measurements
+//! between devices at known distances, as described in APS014:
+//!
+//! 1. Place three devices at known distances from each other, for example in
+//!    a triangle. Configure their antenna delays, for example to the values
+//!    they are currently using.
+//! 2. Perform a number of double-sided range measurements between each pair of
+//!    devices (see [`ranging`]), and add the results to a [`Calibration`].
+//! 3. Call [`Calibration::solve`] to get the corrected antenna delays of all
+//!    devices, and pass them to [`DW1000::set_antenna_delay`] using
+//!    [`AntennaDelay::to_register`].
+//!
+//! More than three devices can be calibrated at once, as long as enough pairs
+//! are measured. If a device with a known antenna delay is available, it can
+//! be marked using [`Calibration::set_reference`]. Then a single other device
+//! can be calibrated from measurements to it alone.
+//!
+//! Any range bias (see [`range_bias`]) at the calibration distances ends up in
+//! the antenna delays, so the calibration should be done under conditions
+//! similar to those the devices are used in.
+//!
+//! [`DW1000::set_antenna_delay`]: crate::DW1000::set_antenna_delay
+//! [`ranging`]: crate::ranging
+//! [`range_bias`]: crate::range_bias
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    math::{round, solve_linear},
+    ranging::RangingResult,
+    time::Duration,
+};
+
+/// The antenna delays of a device
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct AntennaDelay {
+    /// The RX antenna delay
+    pub rx: Duration,
+
+    /// The TX antenna delay
+    pub tx: Duration,
+}
+
+impl AntennaDelay {
+    /// Creates an instance from the values passed to
+    /// [`DW1000::set_antenna_delay`]
+    ///
+    /// [`DW1000::set_antenna_delay`]: crate::DW1000::set_antenna_delay
+    pub fn from_register(rx_delay: u16, tx_delay: u16) -> Self {
+        AntennaDelay {
+            rx: Duration::new(rx_delay as u64).unwrap(),
+            tx: Duration::new(tx_delay as u64).unwrap(),
+        }
+    }
+
+    /// Returns the RX and TX delays as passed to [`DW1000::set_antenna_delay`]
+    ///
+    /// The delays returned by [`Calibration::solve`] always fit into the
+    /// registers. Larger values are truncated.
+    ///
+    /// [`DW1000::set_antenna_delay`]: crate::DW1000::set_antenna_delay
+    pub fn to_register(&self) -> (u16, u16) {
+        (self.rx.value() as u16, self.tx.value() as u16)
+    }
+}
+
+/// Determines the antenna delays of `N` devices
+///
+/// See the [module documentation] for details.
+///
+/// [module documentation]: index.html
+#[derive(Debug)]
+pub struct Calibration<const N: usize> {
+    configured: [AntennaDelay; N],
+    reference: [bool; N],
+
+    /// For each pair of devices, the sum of the differences between the
+    /// measured and the actual time of flight, in DW1000 time units
+    excess: [[f64; N]; N],
+
+    /// For each pair of devices, the number of measurements
+    count: [[u32; N]; N],
+}
+
+impl<const N: usize> Calibration<N> {
+    /// Creates a new calibration
+    ///
+    /// `configured` are the antenna delays the devices are configured with
+    /// while the measurements are taken.
+    pub fn new(configured: [AntennaDelay; N]) -> Self {
+        Calibration {
+            configured,
+            reference: [false; N],
+            excess: [[0.0; N]; N],
+            count: [[0; N]; N],
+        }
+    }
+
+    /// Marks a device as a reference
+    ///
+    /// The configured antenna delays of a reference device are known to be
+    /// correct, and are returned unchanged by [`Calibration::solve`].
+    ///
+    /// # Panics
+    ///
+    /// Panics, if `device` is not less than `N`.
+    pub fn set_reference(&mut self, device: usize) {
+        self.reference[device] = true;
+    }
+
+    /// Adds a range measurement between two devices
+    ///
+    /// `actual_distance_mm` is the actual distance between the devices, and
+    /// `result` the result of the double-sided range measurement between them,
+    /// as returned by [`compute_ds_twr`] or [`compute_ds_twr_from_report`].
+    ///
+    /// # Panics
+    ///
+    /// Panics, if `a` and `b` are equal, or not less than `N`.
+    ///
+    /// [`compute_ds_twr`]: crate::ranging::compute_ds_twr
+    /// [`compute_ds_twr_from_report`]: crate::ranging::compute_ds_twr_from_report
+    pub fn add_measurement(
+        &mut self,
+        a: usize,
+        b: usize,
+        actual_distance_mm: u64,
+        result: &RangingResult,
+    ) {
+        assert!(a != b, "Can't add a measurement from a device to itself");
+        let (a, b) = (a.min(b), a.max(b));
+
+        // Both in DW1000 time units, like the antenna delays
+        const SPEED_OF_LIGHT: f64 = 299_792_458.0; // m/s
+        let measured = result.time_of_flight_ps as f64 * 64.0 / 1000.0;
+        let actual = actual_distance_mm as f64 / SPEED_OF_LIGHT * 64e6;
+
+        self.excess[a][b] += measured - actual;
+        self.count[a][b] += 1;
+    }
+
+    /// Returns the number of measurements added between two devices
+    pub fn measurement_count(&self, a: usize, b: usize) -> u32 {
+        self.count[a.min(b)][a.max(b)]
+    }
+
+    /// Computes the antenna delays of all devices
+    ///
+    /// Every measured time of flight is off by the average of the errors of
+    /// the total (RX plus TX) antenna delays of both devices. This function
+    /// finds the errors that fit all measurements best, and splits the
+    /// corrected total delay of each device equally between RX and TX.
+    pub fn solve(&self) -> Result<[AntennaDelay; N], CalibrationError> {
+        // Every pair of devices `i` and `j` results in the equation
+        // `x_i + x_j = 2 * e_ij`, with `x` being the errors of the total
+        // antenna delays, and `e_ij` the average excess time of flight. Build
+        // the weighted normal equations for the least-squares solution.
+        let mut a = [[0.0; N]; N];
+        let mut b = [0.0; N];
+        for i in 0..N {
+            for j in i + 1..N {
+                let count = self.count[i][j] as f64;
+                let excess = self.excess[i][j];
+
+                a[i][i] += count;
+                a[j][j] += count;
+                a[i][j] += count;
+                a[j][i] += count;
+                b[i] += 2.0 * excess;
+                b[j] += 2.0 * excess;
+            }
+        }
+
+        // The errors of reference devices are zero
+        for (k, &reference) in self.reference.iter().enumerate() {
+            if reference {
+                for row in a.iter_mut() {
+                    row[k] = 0.0;
+                }
+                a[k] = [0.0; N];
+                a[k][k] = 1.0;
+                b[k] = 0.0;
+            }
+        }
+
+        let errors = solve_linear(a, b, 1e-9).ok_or(CalibrationError::Underdetermined)?;
+
+        let mut delays = self.configured;
+        for (device, (delay, error)) in delays.iter_mut().zip(errors).enumerate() {
+            if self.reference[device] {
+                continue;
+            }
+
+            let total = (delay.rx.value() + delay.tx.value()) as f64 + error;
+            let total = round(total);
+
+            let rx = total / 2;
+            let tx = total - rx;
+
+            let out_of_range = |value: i64| value < 0 || value > u16::MAX as i64;
+            if out_of_range(rx) || out_of_range(tx) {
+                return Err(CalibrationError::OutOfRange { device });
+            }
+
+            *delay = AntennaDelay::from_register(rx as u16, tx as u16);
+        }
+
+        Ok(delays)
+    }
+}
+
+/// Returned from [`Calibration::solve`] in case of an error
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CalibrationError {
+    /// The measurements are not sufficient to determine all antenna delays
+    ///
+    /// Every device needs to be measured against at least two other devices
+    /// that are measured against each other, or against a reference device.
+    Underdetermined,
+
+    /// The antenna delays of a device don't fit into the delay registers
+    OutOfRange {
+        /// The index of the device
+        device: usize,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ranging::{compute_ds_twr, DsTwrFinal, RxMessage},
+        time::Instant,
+    };
+
+    /// Simulates a double-sided range measurement from recorded timestamps
+    ///
+    /// `excess` is the error of the time of flight caused by wrong antenna
+    /// delays, in DW1000 time units.
+    fn measure(distance_mm: u64, excess: u64) -> RangingResult {
+        let time_of_flight = distance_mm * 64_000_000 / 299_792_458 + excess;
+
+        let poll_reply_time = 300_000;
+        let response_reply_time = 500_000;
+        let response_tx_time = 1_000_000;
+
+        let final_message = RxMessage {
+            rx_time: Instant::new(response_tx_time + 2 * time_of_flight + response_reply_time)
+                .unwrap(),
+            source: None,
+            payload: DsTwrFinal {
+                poll_round_trip_time: Duration::new(2 * time_of_flight + poll_reply_time).unwrap(),
+                poll_reply_time: Duration::new(poll_reply_time).unwrap(),
+                response_tx_time: Instant::new(response_tx_time).unwrap(),
+                response_reply_time: Duration::new(response_reply_time).unwrap(),
+            },
+        };
+
+        compute_ds_twr(&final_message).unwrap()
+    }
+
+    fn assert_total_close(delay: &AntennaDelay, expected: u64) {
+        let total = delay.rx.value() + delay.tx.value();
+        assert!(total.abs_diff(expected) <= 2, "{} != {}", total, expected);
+    }
+
+    #[test]
+    fn three_node() {
+        let configured = AntennaDelay::from_register(16000, 16000);
+        // The actual total delays of the three devices
+        let actual = [32600, 33000, 32800];
+
+        let mut calibration = Calibration::new([configured; 3]);
+        for (a, b, distance_mm) in [(0, 1, 5000), (0, 2, 7000), (1, 2, 3000)] {
+            for _ in 0..10 {
+                let excess = (actual[a] + actual[b] - 2 * 32000) / 2;
+                calibration.add_measurement(a, b, distance_mm, &measure(distance_mm, excess));
+            }
+        }
+        assert_eq!(calibration.measurement_count(2, 0), 10);
+
+        let delays = calibration.solve().unwrap();
+        for (delay, actual) in delays.iter().zip(actual) {
+            assert_total_close(delay, actual);
+            assert!(delay.rx.value().abs_diff(delay.tx.value()) <= 1);
+        }
+    }
+
+    #[test]
+    fn two_node_with_reference() {
+        let configured = AntennaDelay::from_register(16436, 16436);
+
+        let mut calibration = Calibration::new([configured; 2]);
+        calibration.set_reference(0);
+        calibration.add_measurement(1, 0, 4000, &measure(4000, 150));
+
+        let delays = calibration.solve().unwrap();
+        assert_eq!(delays[0], configured);
+        assert_total_close(&delays[1], 2 * 16436 + 300);
+    }
+
+    #[test]
+    fn two_node_without_reference_is_underdetermined() {
+        let configured = AntennaDelay::from_register(16436, 16436);
+
+        let mut calibration = Calibration::new([configured; 2]);
+        calibration.add_measurement(0, 1, 4000, &measure(4000, 150));
+
+        assert_eq!(calibration.solve(), Err(CalibrationError::Underdetermined));
+    }
+}
@@ -4,7 +4,7 @@
 //! The configs are passed to the send and receive functions.
 
 use crate::Error;
-use embedded_hal::spi::SpiDevice;
+use embedded_hal::spi::ErrorType;
 use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
 
@@ -26,6 +26,8 @@ pub struct TxConfig {
     pub sfd_sequence: SfdSequence,
     /// When true, a CRC will be appended to the message
     pub append_crc: bool,
+    /// The PHR mode, which determines the maximum frame length.
+    pub phr_mode: PhrMode,
 }
 
 impl Default for TxConfig {
@@ -38,6 +40,7 @@ impl Default for TxConfig {
             channel: Default::default(),
             sfd_sequence: Default::default(),
             append_crc: true,
+            phr_mode: Default::default(),
         }
     }
 }
@@ -47,13 +50,14 @@ impl Default for TxConfig {
 pub struct RxConfig {
     /// The bitrate that will be used for reception.
     pub bitrate: BitRate,
-    /// Enable frame filtering
+    /// Frame filtering
     ///
-    /// If true, only frames directly addressed to this node and broadcasts will
-    /// be received.
+    /// If `Some`, only frames directly addressed to this node and broadcasts
+    /// will be received, and only if their frame type is allowed by the
+    /// filter. If `None`, all frames will be received.
     ///
-    /// Defaults to `true`.
-    pub frame_filtering: bool,
+    /// Defaults to `Some(FrameFilter::default())`.
+    pub frame_filtering: Option<FrameFilter>,
     /// Sets the PRF value of the reception
     pub pulse_repetition_frequency: PulseRepetitionFrequency,
     /// The expected preamble length.
@@ -68,18 +72,236 @@ pub struct RxConfig {
     pub sfd_sequence: SfdSequence,
     /// When true, a CRC will be expected to be appended to the message
     pub append_crc: bool,
+    /// The frame wait timeout
+    ///
+    /// If `Some`, the receiver is turned off when no frame has been received
+    /// within the given time after enabling it, and the receive operation
+    /// fails with [`Error::FrameWaitTimeout`]. The value is given in units of
+    /// 512/499.2 MHz (~1.026 µs).
+    ///
+    /// Defaults to `None`, which means the receiver waits indefinitely.
+    pub frame_wait_timeout: Option<u16>,
+    /// The preamble detection timeout
+    ///
+    /// If `Some`, the receiver is turned off when no preamble has been
+    /// detected within the given time, and the receive operation fails with
+    /// [`Error::PreambleDetectionTimeout`]. The value is given in units of PAC
+    /// size symbols (see [`PreambleLength::get_recommended_pac_size`]).
+    ///
+    /// Defaults to `None`, which disables the timeout.
+    pub preamble_detection_timeout: Option<u16>,
+    /// The SFD detection timeout
+    ///
+    /// If `Some`, the receiver is turned off when no SFD has been detected
+    /// within the given number of preamble symbols after the preamble was
+    /// detected, and the receive operation fails with [`Error::SfdTimeout`].
+    /// The value must not be zero.
+    ///
+    /// Defaults to `None`, which uses the chip's default of 4161 symbols.
+    pub sfd_timeout: Option<u16>,
+    /// Automatic acknowledgement
+    ///
+    /// If `Some`, the DW1000 automatically sends an ACK frame for every
+    /// received data or MAC command frame that has the ack request bit set.
+    /// The value is the turnaround time between receiving the frame and
+    /// sending the ACK, in preamble symbols. The ACK is sent with the bitrate,
+    /// PRF and preamble length of this config.
+    ///
+    /// This requires frame filtering to be enabled.
+    ///
+    /// Defaults to `None`, which disables automatic acknowledgement.
+    pub auto_ack: Option<u8>,
+    /// The PHR mode, which determines the maximum frame length.
+    ///
+    /// This must match the PHR mode used by the sender.
+    pub phr_mode: PhrMode,
+    /// SNIFF mode
+    ///
+    /// If `Some`, the receiver is duty-cycled while it is hunting for a
+    /// preamble, which reduces the power consumption considerably. See
+    /// [`SniffMode`] for the constraints on the on and off times.
+    ///
+    /// Defaults to `None`, which keeps the receiver on continuously.
+    pub sniff_mode: Option<SniffMode>,
 }
 
 impl Default for RxConfig {
     fn default() -> Self {
         Self {
             bitrate: Default::default(),
-            frame_filtering: true,
+            frame_filtering: Some(FrameFilter::default()),
             pulse_repetition_frequency: Default::default(),
             expected_preamble_length: Default::default(),
             channel: Default::default(),
             sfd_sequence: Default::default(),
             append_crc: true,
+            frame_wait_timeout: None,
+            preamble_detection_timeout: None,
+            sfd_timeout: None,
+            auto_ack: None,
+            phr_mode: Default::default(),
+            sniff_mode: None,
+        }
+    }
+}
+
+impl RxConfig {
+    /// Checks whether the config is valid
+    ///
+    /// `double_buffered` indicates whether the config is going to be used
+    /// for receiving in double buffered mode.
+    pub fn validate<SPI>(&self, double_buffered: bool) -> Result<(), Error<SPI>>
+    where
+        SPI: ErrorType,
+    {
+        // Really weird thing about double buffering I can't find anything about.
+        // When a message is received in double buffer mode that should be filtered out,
+        // the radio gives a really short fake interrupt.
+        // This messes up all the logic, so unless a solution can be found we simply don't support it.
+        if double_buffered && self.frame_filtering.is_some() {
+            return Err(Error::RxConfigFrameFilteringUnsupported);
+        }
+
+        // A filter that doesn't let anything through is most likely a mistake.
+        if let Some(filter) = self.frame_filtering {
+            if !filter.allows_any() {
+                return Err(Error::InvalidConfiguration);
+            }
+        }
+
+        // A SFD timeout of zero is not allowed according to the user manual.
+        if self.sfd_timeout == Some(0) {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        // The DW1000 only sends ACKs for frames that passed the frame filter.
+        if self.auto_ack.is_some() && self.frame_filtering.is_none() {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        if let Some(sniff_mode) = self.sniff_mode {
+            if !sniff_mode.fits_preamble(self.expected_preamble_length) {
+                return Err(Error::InvalidConfiguration);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+/// Frame filter configuration
+///
+/// Selects which frame types pass the frame filter. Frames of types that are
+/// not allowed are discarded by the DW1000.
+pub struct FrameFilter {
+    /// Behave as PAN coordinator
+    ///
+    /// If true, frames without a destination address are accepted, as long as
+    /// their source PAN ID matches our own.
+    pub behave_as_coordinator: bool,
+    /// Allow beacon frames
+    pub allow_beacon: bool,
+    /// Allow data frames
+    pub allow_data: bool,
+    /// Allow acknowledgement frames
+    pub allow_ack: bool,
+    /// Allow MAC command frames
+    pub allow_mac_command: bool,
+    /// Allow frames with the reserved frame types 6 and 7
+    pub allow_reserved: bool,
+    /// Allow frames with frame type 4 (multipurpose frames in IEEE 802.15.4-2015)
+    pub allow_frame_type_4: bool,
+    /// Allow frames with frame type 5 (fragment frames in IEEE 802.15.4-2015)
+    pub allow_frame_type_5: bool,
+}
+
+impl FrameFilter {
+    /// Returns true if the filter allows at least one frame type
+    pub fn allows_any(&self) -> bool {
+        self.allow_beacon
+            || self.allow_data
+            || self.allow_ack
+            || self.allow_mac_command
+            || self.allow_reserved
+            || self.allow_frame_type_4
+            || self.allow_frame_type_5
+    }
+}
+
+impl Default for FrameFilter {
+    fn default() -> Self {
+        Self {
+            behave_as_coordinator: false,
+            allow_beacon: true,
+            allow_data: true,
+            allow_ack: true,
+            allow_mac_command: true,
+            allow_reserved: false,
+            allow_frame_type_4: false,
+            allow_frame_type_5: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+/// SNIFF mode configuration
+///
+/// In SNIFF mode, the receiver alternates between listening for a preamble
+/// for the on time, and being switched off for the off time. Once a preamble
+/// is detected, the receiver stays on to receive the frame.
+///
+/// To not miss any frames, the preamble must be long enough to contain a full
+/// off time and two full on times, as a preamble that starts during an on
+/// time might not be detected until the next one. This is checked by
+/// [`RxConfig::validate`], based on the expected preamble length and its
+/// recommended PAC size (see [`PreambleLength::get_recommended_pac_size`]).
+pub struct SniffMode {
+    /// The on time, in units of the PAC size
+    ///
+    /// The DW1000 internally adds one PAC to this. Must be between 1 and 15.
+    pub on_time: u8,
+    /// The off time, in units of approximately 1 µs
+    ///
+    /// Must not be zero.
+    pub off_time_us: u8,
+}
+
+impl SniffMode {
+    /// Returns true if no preamble of the given length can be missed
+    pub fn fits_preamble(&self, preamble_length: PreambleLength) -> bool {
+        if self.on_time == 0 || self.on_time > 15 || self.off_time_us == 0 {
+            return false;
+        }
+
+        let pac_size = preamble_length.get_recommended_pac_size() as u32;
+        let on_time = (self.on_time as u32 + 1) * pac_size;
+        // The off time is counted in units of 128 system clock cycles, which
+        // is slightly longer than a preamble symbol. Round up to be safe.
+        let off_time = (self.off_time_us as u32 * 33).div_ceil(32);
+
+        off_time + 2 * on_time <= preamble_length.symbols() as u32
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, TryFromPrimitive)]
+#[repr(u8)]
+/// The PHY header mode
+pub enum PhrMode {
+    /// Standard IEEE 802.15.4 PHY header, for frames of up to 127 bytes.
+    #[default]
+    Standard = 0b00,
+    /// Decawave proprietary long frames mode, for frames of up to 1023 bytes.
+    /// This is not compatible with standard IEEE 802.15.4 devices.
+    Extended = 0b11,
+}
+
+impl PhrMode {
+    /// Gets the maximum frame length in bytes, including the CRC.
+    pub fn max_frame_len(&self) -> usize {
+        match self {
+            PhrMode::Standard => 127,
+            PhrMode::Extended => 1023,
         }
     }
 }
@@ -137,7 +359,7 @@ impl PulseRepetitionFrequency {
     /// Gets the recommended value for the drx_tune2 register based on the PRF and PAC size
     pub fn get_recommended_drx_tune2<SPI>(&self, pac_size: u8) -> Result<u32, Error<SPI>>
     where
-        SPI: SpiDevice,
+        SPI: ErrorType,
     {
         // Values taken from Table 33 of the DW1000 User Manual.
         match (self, pac_size) {
@@ -206,6 +428,20 @@ pub enum PreambleLength {
 }
 
 impl PreambleLength {
+    /// Gets the number of preamble symbols
+    pub fn symbols(&self) -> u16 {
+        match self {
+            PreambleLength::Symbols64 => 64,
+            PreambleLength::Symbols128 => 128,
+            PreambleLength::Symbols256 => 256,
+            PreambleLength::Symbols512 => 512,
+            PreambleLength::Symbols1024 => 1024,
+            PreambleLength::Symbols1536 => 1536,
+            PreambleLength::Symbols2048 => 2048,
+            PreambleLength::Symbols4096 => 4096,
+        }
+    }
+
     /// Gets the recommended PAC size based on the preamble length.
     pub fn get_recommended_pac_size(&self) -> u8 {
         // Values are taken from Table 6 of the DW1000 User manual
@@ -224,7 +460,7 @@ impl PreambleLength {
     /// Gets the recommended drx_tune1b register value based on the preamble length and the bitrate.
     pub fn get_recommended_drx_tune1b<SPI>(&self, bitrate: BitRate) -> Result<u16, Error<SPI>>
     where
-        SPI: SpiDevice,
+        SPI: ErrorType,
     {
         // Values are taken from Table 32 of the DW1000 User manual
         match (self, bitrate) {
@@ -476,4 +712,202 @@ impl UwbChannel {
             UwbChannel::Channel4 | UwbChannel::Channel7 => 0xBC,
         }
     }
+
+    /// Gets the center frequency of the channel in Hz
+    pub fn get_center_frequency_hz(&self) -> f32 {
+        match self {
+            UwbChannel::Channel1 => 3494.4e6,
+            UwbChannel::Channel2 | UwbChannel::Channel4 => 3993.6e6,
+            UwbChannel::Channel3 => 4492.8e6,
+            UwbChannel::Channel5 | UwbChannel::Channel7 => 6489.6e6,
+        }
+    }
+
+    /// Gets the recommended TX power for the channel and PRF
+    ///
+    /// `smart` selects between the values for smart TX power control and the
+    /// values for manual TX power control.
+    pub fn get_recommended_tx_power(
+        &self,
+        pulse_repetition_frequency: PulseRepetitionFrequency,
+        smart: bool,
+    ) -> TxPower {
+        use PulseRepetitionFrequency::*;
+
+        // Values based on the TX_POWER reference values of the DW1000 User Manual
+        let value = match (self, pulse_repetition_frequency, smart) {
+            (UwbChannel::Channel1 | UwbChannel::Channel2, Mhz16, true) => 0x15355575,
+            (UwbChannel::Channel1 | UwbChannel::Channel2, Mhz64, true) => 0x07274767,
+            (UwbChannel::Channel3, Mhz16, true) => 0x0F2F4F6F,
+            (UwbChannel::Channel3, Mhz64, true) => 0x2B4B6B8B,
+            (UwbChannel::Channel4, Mhz16, true) => 0x1F1F3F5F,
+            (UwbChannel::Channel4, Mhz64, true) => 0x3A5A7A9A,
+            (UwbChannel::Channel5, Mhz16, true) => 0x0E082848,
+            (UwbChannel::Channel5, Mhz64, true) => 0x25456585,
+            (UwbChannel::Channel7, Mhz16, true) => 0x32527292,
+            (UwbChannel::Channel7, Mhz64, true) => 0x5171B1D1,
+            (UwbChannel::Channel1 | UwbChannel::Channel2, Mhz16, false) => 0x75757575,
+            (UwbChannel::Channel1 | UwbChannel::Channel2, Mhz64, false) => 0x67676767,
+            (UwbChannel::Channel3, Mhz16, false) => 0x6F6F6F6F,
+            (UwbChannel::Channel3, Mhz64, false) => 0x8B8B8B8B,
+            (UwbChannel::Channel4, Mhz16, false) => 0x5F5F5F5F,
+            (UwbChannel::Channel4, Mhz64, false) => 0x9A9A9A9A,
+            (UwbChannel::Channel5, Mhz16, false) => 0x48484848,
+            (UwbChannel::Channel5, Mhz64, false) => 0x85858585,
+            (UwbChannel::Channel7, Mhz16, false) => 0x92929292,
+            (UwbChannel::Channel7, Mhz64, false) => 0xD1D1D1D1,
+        };
+
+        TxPower::from_register(value, smart)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, TryFromPrimitive)]
+#[repr(u8)]
+/// The coarse gain of the transmitter, set by the DA (digital to analog) stage
+pub enum CoarseGain {
+    /// 15 dB of gain
+    Db15 = 0b000,
+    /// 12.5 dB of gain
+    Db12_5 = 0b001,
+    /// 10 dB of gain
+    Db10 = 0b010,
+    /// 7.5 dB of gain
+    Db7_5 = 0b011,
+    /// 5 dB of gain
+    Db5 = 0b100,
+    /// 2.5 dB of gain
+    Db2_5 = 0b101,
+    /// 0 dB of gain
+    Db0 = 0b110,
+    /// The output is turned off
+    Off = 0b111,
+}
+
+impl CoarseGain {
+    /// Gets the gain in dB, or `None` if the output is turned off
+    pub fn gain_db(&self) -> Option<f32> {
+        match self {
+            CoarseGain::Off => None,
+            gain => Some(15.0 - 2.5 * *gain as u8 as f32),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+/// A TX power setting, as used by a single byte of the TX_POWER register
+pub struct TxPowerLevel {
+    /// The coarse gain
+    pub coarse: CoarseGain,
+    /// The fine gain of the mixer, in steps of 0.5 dB
+    ///
+    /// Only values from 0 (0 dB) up to 31 (15.5 dB) are valid. Higher values
+    /// are truncated.
+    pub fine: u8,
+}
+
+impl TxPowerLevel {
+    /// Creates the power level from the register representation
+    pub fn from_register(value: u8) -> Self {
+        TxPowerLevel {
+            // All 3 bit values are valid, so this can't fail
+            coarse: CoarseGain::try_from(value >> 5).unwrap(),
+            fine: value & 0x1F,
+        }
+    }
+
+    /// Gets the register representation of the power level
+    pub fn to_register(&self) -> u8 {
+        (self.coarse as u8) << 5 | (self.fine & 0x1F)
+    }
+
+    /// Gets the total gain in dB, or `None` if the output is turned off
+    pub fn gain_db(&self) -> Option<f32> {
+        self.coarse
+            .gain_db()
+            .map(|coarse| coarse + (self.fine & 0x1F) as f32 * 0.5)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+/// The TX power configuration
+///
+/// The DW1000 can either control the TX power by itself (smart TX power
+/// control) or use fixed values for the different parts of a frame.
+pub enum TxPower {
+    /// Smart TX power control
+    ///
+    /// Short frames may be sent with a higher power, as long as the average
+    /// power over a millisecond stays within the regulatory limits. This only
+    /// works at 6.8 Mbps.
+    Smart {
+        /// The power for frames longer than 0.5 ms
+        normal: TxPowerLevel,
+        /// The power for frames between 0.25 ms and 0.5 ms
+        boost_500us: TxPowerLevel,
+        /// The power for frames between 0.125 ms and 0.25 ms
+        boost_250us: TxPowerLevel,
+        /// The power for frames shorter than 0.125 ms
+        boost_125us: TxPowerLevel,
+    },
+    /// Manual TX power control
+    Manual {
+        /// The power for the PHY header
+        phr: TxPowerLevel,
+        /// The power for the synchronisation header (preamble and SFD) and
+        /// the data
+        shr_data: TxPowerLevel,
+    },
+}
+
+impl TxPower {
+    /// Creates the TX power from the value of the TX_POWER register
+    ///
+    /// `smart` needs to be the same as the smart TX power setting the value is
+    /// meant for, as the register layout depends on it.
+    pub fn from_register(value: u32, smart: bool) -> Self {
+        let [b0, b1, b2, b3] = value.to_le_bytes();
+
+        if smart {
+            TxPower::Smart {
+                normal: TxPowerLevel::from_register(b0),
+                boost_500us: TxPowerLevel::from_register(b1),
+                boost_250us: TxPowerLevel::from_register(b2),
+                boost_125us: TxPowerLevel::from_register(b3),
+            }
+        } else {
+            TxPower::Manual {
+                phr: TxPowerLevel::from_register(b1),
+                shr_data: TxPowerLevel::from_register(b2),
+            }
+        }
+    }
+
+    /// Gets the value for the TX_POWER register
+    pub fn to_register(&self) -> u32 {
+        match self {
+            TxPower::Smart {
+                normal,
+                boost_500us,
+                boost_250us,
+                boost_125us,
+            } => u32::from_le_bytes([
+                normal.to_register(),
+                boost_500us.to_register(),
+                boost_250us.to_register(),
+                boost_125us.to_register(),
+            ]),
+            TxPower::Manual { phr, shr_data } => {
+                // The first and last byte are unused in manual mode. The
+                // recommended values set them to the same as the others.
+                let shr_data = shr_data.to_register();
+                u32::from_le_bytes([shr_data, phr.to_register(), shr_data, shr_data])
+            }
+        }
+    }
+
+    /// Returns true if this is a smart TX power configuration
+    pub fn is_smart(&self) -> bool {
+        matches!(self, TxPower::Smart { .. })
+    }
 }
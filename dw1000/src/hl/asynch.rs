@@ -0,0 +1,594 @@
+//! Async interface to the DW1000
+//!
+//! This module provides an async variant of the [high-level interface], built
+//! on top of [`embedded-hal-async`]. Instead of polling SYS_STATUS, the
+//! methods that wait for an operation to finish await the DW1000's IRQ
+//! output, so the executor can do something else (or sleep) in the meantime.
+//!
+//! The API mirrors the blocking one, and uses the same state types. It
+//! currently covers sending and single buffered receiving.
+//!
+//! The IRQ signal is expected to be active high, which is the DW1000's
+//! default. The interrupt mask is managed by this module.
+//!
+//! This module is only available, if the `async` feature is enabled.
+//!
+//! [high-level interface]: super
+//! [`embedded-hal-async`]: https://crates.io/crates/embedded-hal-async
+
+use super::{
+    ready::{build_data_frame, write_frame},
+    receiving::{frame_len, rx_error},
+    sequences, Awake, CrystalTrimmer, Message, RawMessage, ReceiveTime, Receiving, SendTime,
+    TrimmerConfig,
+};
+use crate::{
+    ll, mac, time::Instant, Error, Ready, RxConfig, Sending, SingleBufferReceiving, TxConfig,
+    Uninitialized,
+};
+use byte::BytesExt as _;
+use core::{fmt, num::Wrapping};
+use embedded_hal_async::{delay::DelayNs, digital::Wait, spi::SpiDevice};
+use ieee802154::mac::FooterMode;
+
+/// Entry point to the async DW1000 driver API
+///
+/// This is the async counterpart of [`crate::DW1000`].
+pub struct DW1000<SPI, State> {
+    ll: ll::DW1000<SPI>,
+    seq: Wrapping<u8>,
+    state: State,
+}
+
+// Can't be derived without putting requirements on `SPI`.
+impl<SPI, State> fmt::Debug for DW1000<SPI, State>
+where
+    State: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DW1000 {{ state: ")?;
+        self.state.fmt(f)?;
+        write!(f, ", .. }}")?;
+
+        Ok(())
+    }
+}
+
+impl<SPI> DW1000<SPI, Uninitialized>
+where
+    SPI: SpiDevice,
+{
+    /// Create a new instance of `DW1000`
+    ///
+    /// Requires the SPI peripheral and the chip select pin that are connected
+    /// to the DW1000.
+    pub fn new(spi: SPI) -> Self {
+        DW1000 {
+            ll: ll::DW1000::new(spi),
+            seq: Wrapping(0),
+            state: Uninitialized,
+        }
+    }
+
+    /// Initialize the DW1000
+    ///
+    /// See [`crate::DW1000::init`].
+    pub async fn init<D: DelayNs>(
+        mut self,
+        delay: &mut D,
+    ) -> Result<DW1000<SPI, Ready>, Error<SPI>> {
+        sequences::init!(async, self, delay);
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: Ready,
+        })
+    }
+}
+
+impl<SPI> DW1000<SPI, Ready>
+where
+    SPI: SpiDevice,
+{
+    /// Sets the RX and TX antenna delays
+    pub async fn set_antenna_delay(
+        &mut self,
+        rx_delay: u16,
+        tx_delay: u16,
+    ) -> Result<(), Error<SPI>> {
+        self.ll
+            .lde_rxantd()
+            .write_async(|w| w.value(rx_delay))
+            .await?;
+        self.ll.tx_antd().write_async(|w| w.value(tx_delay)).await?;
+
+        Ok(())
+    }
+
+    /// Sets the crystal trim
+    ///
+    /// Increasing the trim lowers the frequency of the crystal oscillator.
+    /// Only the lower 5 bits of `trim` are used.
+    pub async fn set_crystal_trim(&mut self, trim: u8) -> Result<(), Error<SPI>> {
+        sequences::set_crystal_trim!(async, self, trim)
+    }
+
+    /// Returns the crystal trim
+    pub async fn get_crystal_trim(&mut self) -> Result<u8, Error<SPI>> {
+        Ok(self.ll.fs_xtalt().read_async().await?.xtalt())
+    }
+
+    /// Creates a [`CrystalTrimmer`] for this DW1000
+    ///
+    /// If the OTP memory contains a crystal trim, it is set and used as the
+    /// starting point. Otherwise, the trim that is currently set is used.
+    pub async fn crystal_trimmer(
+        &mut self,
+        config: TrimmerConfig,
+    ) -> Result<CrystalTrimmer, Error<SPI>> {
+        sequences::crystal_trimmer!(async, self, config)
+    }
+
+    /// Sets the network id and address used for sending and receiving
+    pub async fn set_address(
+        &mut self,
+        pan_id: mac::PanId,
+        addr: mac::ShortAddress,
+    ) -> Result<(), Error<SPI>> {
+        self.ll
+            .panadr()
+            .write_async(|w| w.pan_id(pan_id.0).short_addr(addr.0))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the sequence number for the next frame to be sent
+    ///
+    /// This also automatically increases the sequence number.
+    pub fn next_seq(&mut self) -> u8 {
+        let seq = self.seq.0;
+        self.seq += Wrapping(1);
+        seq
+    }
+
+    /// Send an IEEE 802.15.4 MAC frame
+    ///
+    /// See [`crate::DW1000::send`]. Use [`DW1000::wait_transmit`] on the
+    /// returned instance to wait for the transmission to finish.
+    pub async fn send(
+        mut self,
+        data: &[u8],
+        destination: Option<mac::Address>,
+        send_time: SendTime,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, Sending>, Error<SPI>> {
+        let seq = self.next_seq();
+        let source = self.get_address().await?;
+        let frame = build_data_frame(data, destination, source, seq, false);
+
+        self.send_with_writer(|buffer| write_frame(frame, buffer), send_time, config)
+            .await
+    }
+
+    /// Send raw bytes
+    ///
+    /// See [`crate::DW1000::send_raw`]. Unlike there, `writer` is called once
+    /// per 127 bytes of a frame of the [extended PHR mode], so it must write
+    /// the same frame every time.
+    ///
+    /// [extended PHR mode]: crate::configs::PhrMode::Extended
+    pub async fn send_raw(
+        self,
+        writer: impl Fn(&mut [u8]) -> usize,
+        send_time: SendTime,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, Sending>, Error<SPI>> {
+        self.send_with_writer(|buffer| Ok(writer(buffer)), send_time, config)
+            .await
+    }
+
+    /// Like `send_raw`, but with a fallible writer
+    async fn send_with_writer(
+        mut self,
+        writer: impl Fn(&mut [u8]) -> Result<usize, Error<SPI>>,
+        send_time: SendTime,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, Sending>, Error<SPI>> {
+        // Only signal the events that `wait_transmit` checks
+        self.ll.sys_mask().write_async(|w| w.mtxfrs(0b1)).await?;
+
+        self.prepare_transmission(writer, &send_time, &config)
+            .await?;
+        self.start_transmission(&send_time, config.append_crc, false)
+            .await?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: Sending { finished: false },
+        })
+    }
+
+    /// Attempt to receive a single IEEE 802.15.4 MAC frame
+    ///
+    /// See [`crate::DW1000::receive`]. Use [`DW1000::wait_receive`] on the
+    /// returned instance to wait for a frame.
+    pub async fn receive(
+        self,
+        config: RxConfig,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, Error<SPI>> {
+        self.receive_at(ReceiveTime::Now, config).await
+    }
+
+    /// Attempt to receive a single IEEE 802.15.4 MAC frame, starting at the
+    /// given time
+    ///
+    /// See [`crate::DW1000::receive_delayed`].
+    pub async fn receive_delayed(
+        self,
+        time: Instant,
+        config: RxConfig,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, Error<SPI>> {
+        self.receive_at(ReceiveTime::Delayed(time), config).await
+    }
+
+    async fn receive_at(
+        mut self,
+        receive_time: ReceiveTime,
+        config: RxConfig,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, Error<SPI>> {
+        // Only signal the events that `wait_receive` checks
+        self.ll
+            .sys_mask()
+            .write_async(|w| {
+                w.mrxdfr(0b1) // Data Frame Ready
+                    .mrxfce(0b1) // FCS Error
+                    .mrxphe(0b1) // PHY Header Error
+                    .mrxrfsl(0b1) // Reed Solomon Frame Sync Loss
+                    .mrxrfto(0b1) // Receive Frame Wait Timeout
+                    .mrxovrr(0b1) // Overrun
+                    .mrxpto(0b1) // Preamble detection timeout
+                    .mrxsfdto(0b1) // SFD Timeout
+                    .maffrej(0b1) // Automatic Frame Filter Rejection
+            })
+            .await?;
+
+        self.configure_receiver(&config, false, false).await?;
+        self.enable_receiver(receive_time).await?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: SingleBufferReceiving {
+                finished: false,
+                config,
+            },
+        })
+    }
+}
+
+impl<SPI> DW1000<SPI, Sending>
+where
+    SPI: SpiDevice,
+{
+    /// Wait for the transmission to finish
+    ///
+    /// Waits for the IRQ signal between checks of the transmission status.
+    /// Returns the time the frame was sent.
+    pub async fn wait_transmit<IRQ: Wait>(&mut self, irq: &mut IRQ) -> Result<Instant, Error<SPI>> {
+        loop {
+            match self.check_transmit().await {
+                Ok(tx_timestamp) => {
+                    self.state.finished = true;
+                    return Ok(tx_timestamp);
+                }
+                Err(nb::Error::Other(error)) => return Err(error),
+                Err(nb::Error::WouldBlock) => {}
+            }
+
+            irq.wait_for_high().await.map_err(|_| Error::Irq)?;
+        }
+    }
+
+    /// Finishes sending and returns to the `Ready` state
+    ///
+    /// If the send operation has finished, as indicated by `wait_transmit`,
+    /// this is a no-op. If the send operation is still ongoing, it will be
+    /// aborted.
+    pub async fn finish_sending(mut self) -> Result<DW1000<SPI, Ready>, Error<SPI>> {
+        if !self.state.finished {
+            self.force_idle(false).await?;
+            self.reset_tx_flags().await?;
+        }
+
+        // Turn off the external transmit synchronization
+        self.ll.ec_ctrl().modify_async(|_, w| w.ostsm(0)).await?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: Ready,
+        })
+    }
+}
+
+impl<SPI> DW1000<SPI, SingleBufferReceiving>
+where
+    SPI: SpiDevice,
+{
+    /// Wait for a frame to be received
+    ///
+    /// Waits for the IRQ signal between checks of the receive status, then
+    /// decodes the received frame.
+    pub async fn wait_receive<'b, IRQ: Wait>(
+        &mut self,
+        irq: &mut IRQ,
+        buffer: &'b mut [u8],
+    ) -> Result<Message<'b>, Error<SPI>> {
+        let RawMessage { rx_time, bytes } = self.wait_receive_raw(irq, buffer).await?;
+
+        let frame = bytes
+            .read_with(
+                &mut 0,
+                if self.state.get_rx_config().append_crc {
+                    FooterMode::Explicit
+                } else {
+                    FooterMode::None
+                },
+            )
+            .map_err(Error::Frame)?;
+
+        Ok(Message { rx_time, frame })
+    }
+
+    /// Wait for a frame to be received, without decoding it
+    ///
+    /// Waits for the IRQ signal between checks of the receive status.
+    pub async fn wait_receive_raw<'b, IRQ: Wait>(
+        &mut self,
+        irq: &mut IRQ,
+        buffer: &'b mut [u8],
+    ) -> Result<RawMessage<'b>, Error<SPI>> {
+        loop {
+            let sys_status = self.ll.sys_status().read_async().await?;
+
+            // A delayed receive that was started too late
+            if sys_status.hpdwarn() == 0b1 {
+                return Err(Error::DelayedReceiveTooLate);
+            }
+
+            if sys_status.rxdfr() == 0b0 {
+                if let Some(error) = rx_error(&sys_status) {
+                    return Err(error);
+                }
+            } else if sys_status.ldedone() == 0b1 {
+                // Frame is ready and the RX time stamp is available
+                break;
+            } else {
+                // The frame is ready, but the RX time stamp isn't yet. RXDFR
+                // stays set and keeps the IRQ signal high, so wait for the LDE
+                // instead. The mask is reset by the next receive operation.
+                self.ll
+                    .sys_mask()
+                    .modify_async(|_, w| w.mrxdfr(0b0).mldedone(0b1))
+                    .await?;
+            }
+
+            irq.wait_for_high().await.map_err(|_| Error::Irq)?;
+        }
+
+        let rx_time = self.ll.rx_time().read_async().await?.rx_stamp();
+        // `rx_time` comes directly from the register, which should always
+        // contain a 40-bit timestamp.
+        let rx_time = unsafe { Instant::new_unchecked(rx_time) };
+
+        // Read received frame
+        let rx_finfo = self.ll.rx_finfo().read_async().await?;
+        let len = frame_len(&rx_finfo, self.state.config.phr_mode);
+        if buffer.len() < len {
+            return Err(Error::BufferTooSmall { required_len: len });
+        }
+        self.ll.read_rx_data_async(0, &mut buffer[..len]).await?;
+
+        self.clear_rx_status().await?;
+        self.state.finished = true;
+
+        Ok(RawMessage {
+            rx_time,
+            bytes: &buffer[..len],
+        })
+    }
+
+    /// Finishes receiving and returns to the `Ready` state
+    ///
+    /// If the receive operation has finished, as indicated by `wait_receive`,
+    /// this is a no-op. If the receive operation is still ongoing, it will be
+    /// aborted.
+    pub async fn finish_receiving(mut self) -> Result<DW1000<SPI, Ready>, Error<SPI>> {
+        if !self.state.finished {
+            self.force_idle(false).await?;
+        }
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: Ready,
+        })
+    }
+
+    async fn clear_rx_status(&mut self) -> Result<(), Error<SPI>> {
+        self.ll
+            .sys_status()
+            .write_async(|w| {
+                w.rxprd(0b1) // Receiver Preamble Detected
+                    .rxsfdd(0b1) // Receiver SFD Detected
+                    .ldedone(0b1) // LDE Processing Done
+                    .rxphd(0b1) // Receiver PHY Header Detected
+                    .rxphe(0b1) // Receiver PHY Header Error
+                    .rxdfr(0b1) // Receiver Data Frame Ready
+                    .rxfcg(0b1) // Receiver FCS Good
+                    .rxfce(0b1) // Receiver FCS Error
+                    .rxrfsl(0b1) // Receiver Reed Solomon Frame Sync Loss
+                    .rxrfto(0b1) // Receiver Frame Wait Timeout
+                    .ldeerr(0b1) // Leading Edge Detection Processing Error
+                    .rxovrr(0b1) // Receiver Overrun
+                    .rxpto(0b1) // Preamble Detection Timeout
+                    .rxsfdto(0b1) // Receiver SFD Timeout
+                    .rxrscs(0b1) // Receiver Reed-Solomon Correction Status
+                    .rxprej(0b1) // Receiver Preamble Rejection
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl<SPI, State> DW1000<SPI, State>
+where
+    SPI: SpiDevice,
+    State: Awake,
+{
+    /// Returns the network id and address used for sending and receiving
+    pub async fn get_address(&mut self) -> Result<mac::Address, Error<SPI>> {
+        let panadr = self.ll.panadr().read_async().await?;
+
+        Ok(mac::Address::Short(
+            mac::PanId(panadr.pan_id()),
+            mac::ShortAddress(panadr.short_addr()),
+        ))
+    }
+
+    /// Returns the current system time
+    pub async fn sys_time(&mut self) -> Result<Instant, Error<SPI>> {
+        let sys_time = self.ll.sys_time().read_async().await?.value();
+
+        // Since hardware timestamps fit within 40 bits, the following should
+        // never panic.
+        Ok(Instant::new(sys_time).unwrap())
+    }
+
+    /// Provides direct access to the register-level API
+    ///
+    /// Use the `*_async` methods of the register accessors with the async
+    /// SPI interface.
+    pub fn ll(&mut self) -> &mut ll::DW1000<SPI> {
+        &mut self.ll
+    }
+
+    /// Force the DW1000 into IDLE mode
+    ///
+    /// Any ongoing RX/TX operations will be aborted.
+    async fn force_idle(&mut self, double_buffered: bool) -> Result<(), Error<SPI>> {
+        sequences::force_idle!(async, self, double_buffered)
+    }
+
+    /// Reads a word from the OTP memory
+    ///
+    /// See the constants in the [`otp`] module for the addresses of known
+    /// values.
+    ///
+    /// [`otp`]: crate::hl::otp
+    pub async fn read_otp(&mut self, address: u16) -> Result<u32, Error<SPI>> {
+        sequences::read_otp!(async, self, address)
+    }
+
+    /// Applies the receive configuration, without enabling the receiver
+    async fn configure_receiver(
+        &mut self,
+        config: &RxConfig,
+        double_buffered: bool,
+        auto_rx_reenable: bool,
+    ) -> Result<(), Error<SPI>> {
+        sequences::configure_receiver!(async, self, config, double_buffered, auto_rx_reenable)
+    }
+
+    /// Enables the receiver, either right away or at the given time
+    async fn enable_receiver(&mut self, receive_time: ReceiveTime) -> Result<(), Error<SPI>> {
+        sequences::enable_receiver!(async, self, receive_time)
+    }
+
+    /// Writes the frame and the transmit configuration, without starting the
+    /// transmission
+    async fn prepare_transmission(
+        &mut self,
+        writer: impl Fn(&mut [u8]) -> Result<usize, Error<SPI>>,
+        send_time: &SendTime,
+        config: &TxConfig,
+    ) -> Result<(), Error<SPI>> {
+        sequences::prepare_transmission!(async, self, writer, send_time, config)
+    }
+
+    /// Writes a frame of the extended PHR mode into the TX buffer
+    ///
+    /// Holding a buffer for the whole frame across an `.await` would put it
+    /// into the state of every send future, even if it only sends short
+    /// frames. Instead, the frame is written in chunks, running `writer` again
+    /// for each one, so the large buffer is only on the stack while `writer`
+    /// runs.
+    async fn write_long_frame(
+        &mut self,
+        writer: impl Fn(&mut [u8]) -> Result<usize, Error<SPI>>,
+    ) -> Result<usize, Error<SPI>> {
+        let mut offset = 0;
+        loop {
+            let mut chunk = [0; 127];
+            let (len, chunk_len) = long_frame_chunk(&writer, offset, &mut chunk)?;
+
+            self.ll
+                .write_tx_data_async(offset as u16, &chunk[..chunk_len])
+                .await?;
+
+            offset += chunk_len;
+            if offset >= len {
+                return Ok(len);
+            }
+        }
+    }
+
+    /// Starts the prepared transmission
+    async fn start_transmission(
+        &mut self,
+        send_time: &SendTime,
+        append_crc: bool,
+        wait_for_response: bool,
+    ) -> Result<(), Error<SPI>> {
+        sequences::start_transmission!(async, self, send_time, append_crc, wait_for_response)
+    }
+
+    /// Checks whether the transmission has finished, returning its timestamp
+    async fn check_transmit(&mut self) -> nb::Result<Instant, Error<SPI>> {
+        sequences::check_transmit!(async, self)
+    }
+
+    async fn reset_tx_flags(&mut self) -> Result<(), Error<SPI>> {
+        sequences::reset_tx_flags!(async, self)
+    }
+}
+
+/// Runs `writer` and copies the part of the frame starting at `offset` into
+/// `chunk`
+///
+/// Returns the length of the frame and of the copied part.
+#[inline(never)]
+fn long_frame_chunk<SPI>(
+    writer: impl Fn(&mut [u8]) -> Result<usize, Error<SPI>>,
+    offset: usize,
+    chunk: &mut [u8],
+) -> Result<(usize, usize), Error<SPI>>
+where
+    SPI: SpiDevice,
+{
+    // Leave room for the two-octet CRC
+    let mut buffer = [0; 1023 - 2];
+    let len = writer(&mut buffer)?;
+    if len > buffer.len() {
+        return Err(Error::FrameTooLong { max_len: 1023 });
+    }
+
+    let chunk_len = chunk.len().min(len.saturating_sub(offset));
+    chunk[..chunk_len].copy_from_slice(&buffer[offset..offset + chunk_len]);
+
+    Ok((len, chunk_len))
+}
@@ -0,0 +1,207 @@
+//! Trimming of the crystal oscillator
+//!
+//! The frequency of the crystal oscillator can be adjusted using the crystal
+//! trim. Matching the frequency of a node to the frequency of a reference
+//! node reduces the errors of single-sided ranging, and the drift in TDoA
+//! systems. [`CrystalTrimmer`] adjusts the trim based on the clock offsets
+//! measured in received frames.
+
+use embedded_hal::spi::SpiDevice;
+
+use super::sequences;
+use crate::{Error, Ready, DW1000};
+
+/// The maximum value of the crystal trim
+pub const CRYSTAL_TRIM_MAX: u8 = 0x1f;
+
+impl<SPI> DW1000<SPI, Ready>
+where
+    SPI: SpiDevice,
+{
+    /// Sets the crystal trim
+    ///
+    /// Increasing the trim lowers the frequency of the crystal oscillator.
+    /// Only the lower 5 bits of `trim` are used.
+    pub fn set_crystal_trim(&mut self, trim: u8) -> Result<(), Error<SPI>> {
+        sequences::set_crystal_trim!(blocking, self, trim)
+    }
+
+    /// Returns the crystal trim
+    pub fn get_crystal_trim(&mut self) -> Result<u8, Error<SPI>> {
+        Ok(self.ll.fs_xtalt().read()?.xtalt())
+    }
+
+    /// Creates a [`CrystalTrimmer`] for this DW1000
+    ///
+    /// If the OTP memory contains a crystal trim, it is set and used as the
+    /// starting point. Otherwise, the trim that is currently set is used.
+    pub fn crystal_trimmer(&mut self, config: TrimmerConfig) -> Result<CrystalTrimmer, Error<SPI>> {
+        sequences::crystal_trimmer!(blocking, self, config)
+    }
+}
+
+/// Configuration of a [`CrystalTrimmer`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrimmerConfig {
+    /// The change of the clock frequency per trim step, in ppm
+    ///
+    /// This depends on the crystal and the load capacitors. The default is
+    /// a rough value that is typical for DW1000 modules.
+    pub ppm_per_step: f32,
+
+    /// The number of clock offset measurements that are averaged, before the
+    /// trim is adjusted
+    pub samples: u32,
+
+    /// The trim is only adjusted, if the average clock offset is larger than
+    /// this, in ppm
+    pub tolerance_ppm: f32,
+}
+
+impl Default for TrimmerConfig {
+    fn default() -> Self {
+        TrimmerConfig {
+            ppm_per_step: 1.5,
+            samples: 8,
+            tolerance_ppm: 1.0,
+        }
+    }
+}
+
+/// Adjusts the crystal trim towards the clock of a reference node
+///
+/// Feed the clock offsets of frames received from the reference node (see
+/// [`DW1000::read_clock_offset_ppm`]) into [`CrystalTrimmer::add_offset`].
+/// Whenever it returns a new trim, set it using [`DW1000::set_crystal_trim`].
+///
+/// [`DW1000::read_clock_offset_ppm`]: crate::DW1000::read_clock_offset_ppm
+#[derive(Clone, Copy, Debug)]
+pub struct CrystalTrimmer {
+    config: TrimmerConfig,
+    trim: u8,
+    sum_ppm: f32,
+    count: u32,
+    converged: bool,
+}
+
+impl CrystalTrimmer {
+    /// Creates a new trimmer, starting from the given trim
+    pub fn new(trim: u8, config: TrimmerConfig) -> Self {
+        CrystalTrimmer {
+            config,
+            trim: trim.min(CRYSTAL_TRIM_MAX),
+            sum_ppm: 0.0,
+            count: 0,
+            converged: false,
+        }
+    }
+
+    /// Returns the current trim
+    pub fn trim(&self) -> u8 {
+        self.trim
+    }
+
+    /// Indicates whether the last average clock offset was within the
+    /// tolerance
+    pub fn is_converged(&self) -> bool {
+        self.converged
+    }
+
+    /// Adds a measured clock offset
+    ///
+    /// `clock_offset_ppm` is the offset of the reference node's clock, as
+    /// returned by [`DW1000::read_clock_offset_ppm`]. It must have been
+    /// measured with the current trim.
+    ///
+    /// Returns the new trim, if the trim should be changed.
+    ///
+    /// [`DW1000::read_clock_offset_ppm`]: crate::DW1000::read_clock_offset_ppm
+    pub fn add_offset(&mut self, clock_offset_ppm: f32) -> Option<u8> {
+        #[allow(unused_imports)]
+        // Not used on x86, but used on mcu target due to f32 core lib sillyness.
+        use micromath::F32Ext;
+
+        if !clock_offset_ppm.is_finite() {
+            return None;
+        }
+
+        self.sum_ppm += clock_offset_ppm;
+        self.count += 1;
+        if self.count < self.config.samples {
+            return None;
+        }
+
+        let average_ppm = self.sum_ppm / self.count as f32;
+        self.sum_ppm = 0.0;
+        self.count = 0;
+
+        self.converged = average_ppm.abs() <= self.config.tolerance_ppm;
+        if self.converged {
+            return None;
+        }
+
+        // A positive offset means the reference clock is faster. Speed up the
+        // local clock by lowering the trim.
+        let steps = (average_ppm / self.config.ppm_per_step).round() as i32;
+        let trim = (self.trim as i32 - steps).clamp(0, CRYSTAL_TRIM_MAX as i32) as u8;
+
+        if trim == self.trim {
+            return None;
+        }
+
+        self.trim = trim;
+        Some(trim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulates a local clock that is `error_ppm` fast at trim 16
+    fn offset(trim: u8, error_ppm: f32) -> f32 {
+        let local_ppm = error_ppm - (trim as f32 - 16.0) * 1.5;
+        -local_ppm
+    }
+
+    #[test]
+    fn converges() {
+        let mut trimmer = CrystalTrimmer::new(16, TrimmerConfig::default());
+
+        for _ in 0..10 {
+            for _ in 0..TrimmerConfig::default().samples {
+                trimmer.add_offset(offset(trimmer.trim(), 9.2));
+            }
+        }
+
+        assert_eq!(trimmer.trim(), 22);
+        assert!(trimmer.is_converged());
+    }
+
+    #[test]
+    fn averages_before_adjusting() {
+        let config = TrimmerConfig {
+            samples: 4,
+            ..TrimmerConfig::default()
+        };
+        let mut trimmer = CrystalTrimmer::new(16, config);
+
+        assert_eq!(trimmer.add_offset(-5.0), None);
+        assert_eq!(trimmer.add_offset(-7.0), None);
+        assert_eq!(trimmer.add_offset(-6.0), None);
+        assert_eq!(trimmer.add_offset(-6.0), Some(20));
+    }
+
+    #[test]
+    fn stays_in_range() {
+        let config = TrimmerConfig {
+            samples: 1,
+            ..TrimmerConfig::default()
+        };
+        let mut trimmer = CrystalTrimmer::new(2, config);
+
+        assert_eq!(trimmer.add_offset(30.0), Some(0));
+        assert_eq!(trimmer.add_offset(30.0), None);
+        assert_eq!(trimmer.add_offset(-100.0), Some(CRYSTAL_TRIM_MAX));
+    }
+}
@@ -1,12 +1,12 @@
 use crate::ll;
 use core::fmt;
-use embedded_hal::spi::SpiDevice;
+use embedded_hal::spi::ErrorType;
 use ssmarshal;
 
 /// An error that can occur when sending or receiving data
 pub enum Error<SPI>
 where
-    SPI: SpiDevice,
+    SPI: ErrorType,
 {
     /// Error occured while using SPI bus
     Spi(SPI::Error),
@@ -23,6 +23,15 @@ where
         required_len: usize,
     },
 
+    /// Frame too long to be sent
+    ///
+    /// The frame doesn't fit into the maximum frame length of the configured
+    /// PHR mode.
+    FrameTooLong {
+        /// The maximum frame length in bytes, including the CRC
+        max_len: usize,
+    },
+
     /// Receiver Reed Solomon Frame Sync Loss
     ReedSolomon,
 
@@ -62,6 +71,12 @@ where
     /// were likely corrupted.
     DelayedSendPowerUpWarning,
 
+    /// A delayed receive operation could not be started in time
+    ///
+    /// The receiver has not been enabled at the requested time. Finish the
+    /// receive operation and start a new one.
+    DelayedReceiveTooLate,
+
     /// An error occured while serializing or deserializing data
     Ssmarshal(ssmarshal::Error),
 
@@ -80,11 +95,29 @@ where
     /// There are issues with frame filtering in double buffer mode.
     /// So it's not supported now.
     RxConfigFrameFilteringUnsupported,
+
+    /// The OTP programming voltage is not applied
+    OtpVoltageNotOk,
+
+    /// An OTP word could not be programmed
+    ///
+    /// Either the word already has bits set that the new value doesn't have,
+    /// programming didn't finish in time, or the programmed value could not
+    /// be verified.
+    OtpWriteFailed {
+        /// The OTP address of the word
+        address: u16,
+    },
+
+    /// Waiting for the IRQ signal failed
+    ///
+    /// This is only returned by the async interface.
+    Irq,
 }
 
 impl<SPI> From<ll::Error<SPI>> for Error<SPI>
 where
-    SPI: SpiDevice,
+    SPI: ErrorType,
 {
     fn from(error: ll::Error<SPI>) -> Self {
         Error::Spi(error.0)
@@ -93,7 +126,7 @@ where
 
 impl<SPI> From<ssmarshal::Error> for Error<SPI>
 where
-    SPI: SpiDevice,
+    SPI: ErrorType,
 {
     fn from(error: ssmarshal::Error) -> Self {
         Error::Ssmarshal(error)
@@ -104,7 +137,7 @@ where
 // conditionally for `ll::Debug`.
 impl<SPI> fmt::Debug for Error<SPI>
 where
-    SPI: SpiDevice,
+    SPI: ErrorType,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -114,6 +147,9 @@ where
             Error::BufferTooSmall { required_len } => {
                 write!(f, "BufferTooSmall {{ required_len: {:?} }}", required_len,)
             }
+            Error::FrameTooLong { max_len } => {
+                write!(f, "FrameTooLong {{ max_len: {:?} }}", max_len)
+            }
             Error::ReedSolomon => write!(f, "ReedSolomon"),
             Error::FrameWaitTimeout => write!(f, "FrameWaitTimeout"),
             Error::Overrun => write!(f, "Overrun"),
@@ -123,6 +159,7 @@ where
             Error::Frame(error) => write!(f, "Frame({:?})", error),
             Error::DelayedSendTooLate => write!(f, "DelayedSendTooLate"),
             Error::DelayedSendPowerUpWarning => write!(f, "DelayedSendPowerUpWarning"),
+            Error::DelayedReceiveTooLate => write!(f, "DelayedReceiveTooLate"),
             Error::Ssmarshal(error) => write!(f, "Ssmarshal({:?})", error),
             Error::InvalidConfiguration => write!(f, "InvalidConfiguration"),
             Error::RxNotFinished => write!(f, "RxNotFinished"),
@@ -131,6 +168,11 @@ where
             Error::RxConfigFrameFilteringUnsupported => {
                 write!(f, "RxConfigFrameFilteringUnsupported")
             }
+            Error::OtpVoltageNotOk => write!(f, "OtpVoltageNotOk"),
+            Error::OtpWriteFailed { address } => {
+                write!(f, "OtpWriteFailed {{ address: {:?} }}", address)
+            }
+            Error::Irq => write!(f, "Irq"),
         }
     }
 }
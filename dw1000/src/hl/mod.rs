@@ -12,18 +12,29 @@
 use crate::ll;
 use core::{fmt, num::Wrapping};
 
+pub use crystal_trim::*;
 pub use error::*;
 pub use ready::*;
 pub use receiving::*;
+pub use sar::*;
+pub use sleeping::*;
 pub use state_impls::*;
 
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod otp;
+
 mod awake;
+mod crystal_trim;
 mod error;
 mod ready;
 mod receiving;
+mod sar;
 mod sending;
+mod sequences;
 mod sleeping;
 mod state_impls;
+mod test_mode;
 mod uninitialized;
 
 /// Entry point to the DW1000 driver API
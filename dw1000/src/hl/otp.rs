@@ -0,0 +1,374 @@
+//! Access to the one-time programmable (OTP) memory
+//!
+//! The OTP memory contains per-device values that are programmed during
+//! production, like the EUI and calibration values. Some of its words are
+//! reserved for the customer, and can be used to store per-device calibration
+//! values, like antenna delays.
+//!
+//! Reading is available through [`DW1000::read_otp`] and a number of methods
+//! for specific values. Programming is only possible through [`OtpWriter`],
+//! which needs to be unlocked explicitly. Please be aware that programming OTP
+//! memory can't be undone: bits can only be set, never cleared.
+
+use embedded_hal::{delay::DelayNs, spi::SpiDevice};
+
+use super::{sequences, Awake};
+use crate::{Error, Ready, DW1000};
+
+/// OTP address of the EUI (2 words, low word first)
+pub const EUI: u16 = 0x000;
+
+/// OTP address of the alternative EUI (2 words, low word first)
+pub const EUI_2: u16 = 0x002;
+
+/// OTP address of the LDOTUNE calibration value (2 words, low word first)
+pub const LDOTUNE: u16 = 0x004;
+
+/// OTP address of the part ID
+pub const PART_ID: u16 = 0x006;
+
+/// OTP address of the lot ID
+pub const LOT_ID: u16 = 0x007;
+
+/// OTP address of the voltage calibration
+///
+/// Bits 7:0 contain the SAR reading at 3.3 V, bits 15:8 the SAR reading at
+/// 3.7 V.
+pub const VOLTAGE_CALIBRATION: u16 = 0x008;
+
+/// OTP address of the temperature calibration
+///
+/// Bits 7:0 contain the SAR reading at 23 °C, bits 15:8 the SAR reading at
+/// the temperature of the antenna calibration.
+pub const TEMPERATURE_CALIBRATION: u16 = 0x009;
+
+/// OTP address of the TX power configuration
+///
+/// There is one word per channel (1, 2, 3, 4, 5, 7) and PRF (16 MHz, 64 MHz),
+/// in that order. Each word contains the value of the TX_POWER register. The
+/// layout of that value depends on whether smart TX power control is used,
+/// which isn't recorded in the OTP memory.
+pub const TX_POWER: u16 = 0x010;
+
+/// OTP address of the antenna delay
+///
+/// By convention, bits 15:0 contain the delay for a PRF of 16 MHz, bits 31:16
+/// the delay for a PRF of 64 MHz.
+pub const ANTENNA_DELAY: u16 = 0x01C;
+
+/// OTP address of the crystal trim
+///
+/// Bits 4:0 contain the trim value.
+pub const XTAL_TRIM: u16 = 0x01E;
+
+/// The number of attempts to program an OTP word, before giving up
+const PROGRAMMING_ATTEMPTS: usize = 5;
+
+/// The time programming a single OTP word may take, in milliseconds
+const PROGRAMMING_TIMEOUT_MS: u32 = 100;
+
+// Bits of OTP_CTRL that are used to write the OTP mode registers, as named in
+// Decawave's driver. The selection bits are part of the OTPMR field.
+const OTPRDEN: u16 = 0x0001;
+const WRITE_MR: u16 = 0x0008;
+const AUX_UPDATE: u16 = 0x0080;
+const MODE_SEL: u16 = 0x0100;
+const MRA_SEL: u16 = 0x0200;
+const MRB_SEL: u16 = 0x0400;
+
+/// The factory calibration of the voltage and temperature sensors
+///
+/// These are the raw SAR readings taken during production. A value of 0 means
+/// the respective reading has not been programmed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FactoryCalibration {
+    /// The voltage reading at 3.3 V
+    pub voltage_3v3: u8,
+
+    /// The voltage reading at 3.7 V
+    pub voltage_3v7: u8,
+
+    /// The temperature reading at 23 °C
+    pub temperature_23c: u8,
+
+    /// The temperature reading at the time of the antenna calibration
+    pub temperature_antenna_calibration: u8,
+}
+
+impl FactoryCalibration {
+    /// Converts a raw SAR temperature reading into °C
+    pub fn temperature_from_raw(&self, raw: u8) -> f32 {
+        (raw as f32 - self.temperature_23c as f32) * 1.14 + 23.0
+    }
+
+    /// Converts a raw SAR voltage reading into volts
+    pub fn voltage_from_raw(&self, raw: u8) -> f32 {
+        (raw as f32 - self.voltage_3v3 as f32) / 173.0 + 3.3
+    }
+}
+
+impl<SPI, State> DW1000<SPI, State>
+where
+    SPI: SpiDevice,
+    State: Awake,
+{
+    /// Reads a word from the OTP memory
+    ///
+    /// See the constants in the [`otp`] module for the addresses of known
+    /// values.
+    ///
+    /// [`otp`]: crate::hl::otp
+    pub fn read_otp(&mut self, address: u16) -> Result<u32, Error<SPI>> {
+        sequences::read_otp!(blocking, self, address)
+    }
+
+    /// Reads the EUI from the OTP memory
+    ///
+    /// Returns 0, if no EUI has been programmed.
+    pub fn read_otp_eui(&mut self) -> Result<u64, Error<SPI>> {
+        let low = self.read_otp(EUI)?;
+        let high = self.read_otp(EUI + 1)?;
+
+        Ok((high as u64) << 32 | low as u64)
+    }
+
+    /// Reads the part ID from the OTP memory
+    pub fn read_part_id(&mut self) -> Result<u32, Error<SPI>> {
+        self.read_otp(PART_ID)
+    }
+
+    /// Reads the lot ID from the OTP memory
+    pub fn read_lot_id(&mut self) -> Result<u32, Error<SPI>> {
+        self.read_otp(LOT_ID)
+    }
+
+    /// Reads the factory calibration of the voltage and temperature sensors
+    pub fn read_factory_calibration(&mut self) -> Result<FactoryCalibration, Error<SPI>> {
+        let voltage = self.read_otp(VOLTAGE_CALIBRATION)?;
+        let temperature = self.read_otp(TEMPERATURE_CALIBRATION)?;
+
+        Ok(FactoryCalibration {
+            voltage_3v3: voltage as u8,
+            voltage_3v7: (voltage >> 8) as u8,
+            temperature_23c: temperature as u8,
+            temperature_antenna_calibration: (temperature >> 8) as u8,
+        })
+    }
+
+    /// Reads the crystal trim from the OTP memory
+    ///
+    /// Returns `None`, if no crystal trim has been programmed.
+    pub fn read_otp_xtal_trim(&mut self) -> Result<Option<u8>, Error<SPI>> {
+        let trim = self.read_otp(XTAL_TRIM)? as u8 & 0x1f;
+
+        Ok(if trim != 0 { Some(trim) } else { None })
+    }
+}
+
+impl<SPI> DW1000<SPI, Ready>
+where
+    SPI: SpiDevice,
+{
+    /// Unlocks programming of the OTP memory
+    ///
+    /// Programming requires the OTP programming voltage to be applied to the
+    /// VDDIO pin. Returns [`Error::OtpVoltageNotOk`], if it isn't. `delay` is
+    /// used for the waits that the programming sequence requires.
+    ///
+    /// Programming OTP memory is irreversible. Please double-check the
+    /// addresses and values before using the returned [`OtpWriter`].
+    pub fn unlock_otp_programming<'r, D>(
+        &'r mut self,
+        delay: &'r mut D,
+    ) -> Result<OtpWriter<'r, SPI, D>, Error<SPI>>
+    where
+        D: DelayNs,
+    {
+        if self.ll.otp_stat().read()?.otpvpok() == 0 {
+            return Err(Error::OtpVoltageNotOk);
+        }
+
+        Ok(OtpWriter {
+            dw1000: self,
+            delay,
+        })
+    }
+}
+
+/// Programs words into the OTP memory
+///
+/// Created by [`DW1000::unlock_otp_programming`].
+#[derive(Debug)]
+pub struct OtpWriter<'r, SPI, D> {
+    dw1000: &'r mut DW1000<SPI, Ready>,
+    delay: &'r mut D,
+}
+
+impl<'r, SPI, D> OtpWriter<'r, SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Irreversibly programs a word into the OTP memory, and verifies it
+    ///
+    /// Since bits can't be cleared, a word can only be programmed again, if
+    /// the new value keeps all bits set that are already set. Otherwise, or if
+    /// the word can't be verified with a margin read after programming, this
+    /// method returns [`Error::OtpWriteFailed`].
+    pub fn program(&mut self, address: u16, value: u32) -> Result<(), Error<SPI>> {
+        let current = self.dw1000.read_otp(address)?;
+        if current == value {
+            return Ok(());
+        }
+        if current & !value != 0 {
+            return Err(Error::OtpWriteFailed { address });
+        }
+
+        // Run the system clock from the crystal while programming
+        let sysclks = self.dw1000.ll.pmsc_ctrl0().read()?.sysclks();
+        self.dw1000.ll.pmsc_ctrl0().modify(|_, w| w.sysclks(0b01))?;
+
+        let result = self.program_and_verify(address, value);
+
+        // Restore normal reading, even if programming has failed
+        let restored = self.set_mode(OtpMode::Read);
+        self.dw1000
+            .ll
+            .pmsc_ctrl0()
+            .modify(|_, w| w.sysclks(sysclks))?;
+
+        result.and(restored)
+    }
+
+    /// Irreversibly programs the antenna delays into the OTP memory
+    ///
+    /// Uses the convention described at [`ANTENNA_DELAY`].
+    pub fn program_antenna_delay(&mut self, prf16: u16, prf64: u16) -> Result<(), Error<SPI>> {
+        self.program(ANTENNA_DELAY, (prf64 as u32) << 16 | prf16 as u32)
+    }
+
+    /// Irreversibly programs the crystal trim into the OTP memory
+    ///
+    /// Only the lower 5 bits of `trim` are used.
+    pub fn program_xtal_trim(&mut self, trim: u8) -> Result<(), Error<SPI>> {
+        let current = self.dw1000.read_otp(XTAL_TRIM)?;
+        self.program(XTAL_TRIM, current & !0x1f | (trim & 0x1f) as u32)
+    }
+
+    /// Programs a word, following `dwt_otpwriteandverify` of Decawave's driver
+    fn program_and_verify(&mut self, address: u16, value: u32) -> Result<(), Error<SPI>> {
+        self.set_mode(OtpMode::Program)?;
+
+        for _ in 0..PROGRAMMING_ATTEMPTS {
+            self.program_word(address, value)?;
+            if self.dw1000.read_otp(address)? == value {
+                break;
+            }
+        }
+
+        // The word might have been programmed, even if the normal read above
+        // doesn't show it. Only the margin read tells whether all bits are set
+        // reliably.
+        self.set_mode(OtpMode::MarginRead)?;
+        if self.dw1000.read_otp(address)? != value {
+            return Err(Error::OtpWriteFailed { address });
+        }
+
+        Ok(())
+    }
+
+    /// Programs a word, following `_dwt_otpprogword32` of Decawave's driver
+    fn program_word(&mut self, address: u16, value: u32) -> Result<(), Error<SPI>> {
+        if self.dw1000.ll.otp_stat().read()?.otpvpok() == 0 {
+            return Err(Error::OtpVoltageNotOk);
+        }
+
+        self.dw1000.ll.otp_wdat().write(|w| w.value(value))?;
+        self.dw1000.ll.otp_addr().write(|w| w.value(address))?;
+
+        // Start the programming sequence
+        self.dw1000.ll.otp_ctrl().write(|w| w.otpprog(0b1))?;
+        self.dw1000.ll.otp_ctrl().write(|w| w)?;
+
+        let mut waited_ms = 0;
+        while self.dw1000.ll.otp_stat().read()?.otpprgd() == 0 {
+            if waited_ms == PROGRAMMING_TIMEOUT_MS {
+                return Err(Error::OtpWriteFailed { address });
+            }
+            self.delay.delay_ms(1);
+            waited_ms += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the OTP mode registers, following `_dwt_otpsetmrregs` of
+    /// Decawave's driver
+    ///
+    /// The mode registers MRA, MRB and MR are written through OTP_WDAT, and
+    /// are selected using OTP_CTRL.
+    fn set_mode(&mut self, mode: OtpMode) -> Result<(), Error<SPI>> {
+        let (mr, mra, mrb) = mode.registers();
+
+        for (select, value) in [(MRA_SEL, mra), (MRB_SEL, mrb)] {
+            self.write_otp_ctrl(MODE_SEL | select)?;
+            self.dw1000.ll.otp_wdat().write(|w| w.value(value as u32))?;
+            self.write_otp_ctrl(MODE_SEL | select | WRITE_MR)?;
+            self.delay.delay_ms(2);
+            self.write_otp_ctrl(select | WRITE_MR)?;
+            self.write_otp_ctrl(select | AUX_UPDATE | WRITE_MR)?;
+            self.write_otp_ctrl(select | AUX_UPDATE)?;
+            self.write_otp_ctrl(select)?;
+        }
+
+        self.write_otp_ctrl(MODE_SEL)?;
+        self.dw1000.ll.otp_wdat().write(|w| w.value(mr as u32))?;
+        self.write_otp_ctrl(MODE_SEL | WRITE_MR)?;
+        self.delay.delay_ms(2);
+        self.write_otp_ctrl(WRITE_MR)?;
+
+        // Read back the mode registers, which confirms the writes
+        self.write_otp_ctrl(OTPRDEN)?;
+        self.write_otp_ctrl(MRA_SEL | OTPRDEN)?;
+        self.write_otp_ctrl(MRB_SEL | OTPRDEN)?;
+        self.delay.delay_ms(100);
+        self.write_otp_ctrl(OTPRDEN)?;
+        self.write_otp_ctrl(0)?;
+        self.delay.delay_ms(10);
+
+        Ok(())
+    }
+
+    fn write_otp_ctrl(&mut self, value: u16) -> Result<(), Error<SPI>> {
+        self.dw1000.ll.otp_ctrl().write(|w| w.raw_value(value))?;
+        Ok(())
+    }
+}
+
+/// The settings of the OTP mode registers
+///
+/// These are the modes of `_dwt_otpsetmrregs` that `dwt_otpwriteandverify`
+/// uses. The other modes of `_dwt_otpsetmrregs` aren't needed here.
+#[derive(Clone, Copy)]
+enum OtpMode {
+    /// Normal reading, which is the state after reset (mode 0)
+    Read,
+    /// Programming (mode 1)
+    Program,
+    /// Reading with a margin, to verify programmed words (mode 4)
+    ///
+    /// `dwt_otpwriteandverify` selects this mode for the read after
+    /// programming, which only passes if the bits are programmed properly.
+    MarginRead,
+}
+
+impl OtpMode {
+    /// The values of the MR, MRA and MRB registers
+    fn registers(self) -> (u16, u16, u16) {
+        match self {
+            OtpMode::Read => (0x0000, 0x0000, 0x0000),
+            OtpMode::Program => (0x1024, 0x9220, 0x000e),
+            OtpMode::MarginRead => (0x0000, 0x0000, 0x0003),
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use super::AutoDoubleBufferReceiving;
+use super::{otp, AutoDoubleBufferReceiving, AwaitingAck};
 use crate::{
-    configs::SfdSequence, time::Instant, Error, Ready, RxConfig, Sending, SingleBufferReceiving,
-    Sleeping, TxConfig, DW1000,
+    configs::{FrameFilter, PulseRepetitionFrequency, TxPower, UwbChannel},
+    time::Instant,
+    Error, Ready, RxConfig, Sending, SendingWithResponse, SingleBufferReceiving, TxConfig, DW1000,
 };
 use byte::BytesExt as _;
 use core::num::Wrapping;
-use embedded_hal::spi::SpiDevice;
+use embedded_hal::spi::{ErrorType, SpiDevice};
 use ieee802154::mac::{self, FooterMode, FrameSerDesContext};
 
 /// The behaviour of the sync pin
@@ -32,6 +33,17 @@ pub enum SendTime {
     OnSync,
 }
 
+/// The time at which the receiver will be enabled
+pub(crate) enum ReceiveTime {
+    /// As fast as possible
+    Now,
+    /// At the given time
+    ///
+    /// Keeps the receiver off until then, which saves power while waiting for
+    /// a reply that is known not to arrive earlier.
+    Delayed(Instant),
+}
+
 /// The polarity of the irq signal
 pub enum IrqPolarity {
     /// The signal will be high when the interrupt is active
@@ -52,6 +64,72 @@ where
         Ok(())
     }
 
+    /// Sets the TX power
+    ///
+    /// This also enables or disables smart TX power control, depending on the
+    /// variant of `power`.
+    pub fn set_tx_power(&mut self, power: TxPower) -> Result<(), Error<SPI>> {
+        self.ll
+            .sys_cfg()
+            .modify(|_, w| w.dis_stxp(!power.is_smart() as u8))?;
+        self.ll.tx_power().write(|w| w.value(power.to_register()))?;
+
+        Ok(())
+    }
+
+    /// Gets the current TX power
+    pub fn get_tx_power(&mut self) -> Result<TxPower, Error<SPI>> {
+        let smart = self.ll.sys_cfg().read()?.dis_stxp() == 0;
+        let value = self.ll.tx_power().read()?.value();
+
+        Ok(TxPower::from_register(value, smart))
+    }
+
+    /// Sets the recommended TX power for the channel and PRF
+    ///
+    /// If the OTP memory contains a calibrated TX power value for the channel
+    /// and PRF, that value is used. Otherwise, the recommended value from the
+    /// user manual is used (see [`UwbChannel::get_recommended_tx_power`]).
+    ///
+    /// The OTP word is used as the raw value of the TX_POWER register, and
+    /// interpreted according to `smart`. There is no way to tell from the OTP
+    /// memory whether it has been calibrated for smart or manual TX power
+    /// control, so `smart` must match the mode used during calibration (see
+    /// [`otp::TX_POWER`]). Unprogrammed words (all bits cleared, or all bits
+    /// set) are ignored.
+    ///
+    /// Returns the TX power that has been set.
+    pub fn set_recommended_tx_power(
+        &mut self,
+        channel: UwbChannel,
+        pulse_repetition_frequency: PulseRepetitionFrequency,
+        smart: bool,
+    ) -> Result<TxPower, Error<SPI>> {
+        // The OTP memory has one word per channel and PRF
+        let channel_index = match channel {
+            UwbChannel::Channel1 => 0,
+            UwbChannel::Channel2 => 1,
+            UwbChannel::Channel3 => 2,
+            UwbChannel::Channel4 => 3,
+            UwbChannel::Channel5 => 4,
+            UwbChannel::Channel7 => 5,
+        };
+        let prf_index = match pulse_repetition_frequency {
+            PulseRepetitionFrequency::Mhz16 => 0,
+            PulseRepetitionFrequency::Mhz64 => 1,
+        };
+        let calibrated = self.read_otp(otp::TX_POWER + channel_index * 2 + prf_index)?;
+
+        let power = if calibrated != 0 && calibrated != 0xffff_ffff {
+            TxPower::from_register(calibrated, smart)
+        } else {
+            channel.get_recommended_tx_power(pulse_repetition_frequency, smart)
+        };
+        self.set_tx_power(power)?;
+
+        Ok(power)
+    }
+
     /// Sets the network id and address used for sending and receiving
     pub fn set_address(
         &mut self,
@@ -137,51 +215,202 @@ where
     /// is in the `Sending` state, and can be used to wait for the transmission
     /// to finish and check its result.
     pub fn send(
-        mut self,
+        self,
         data: &[u8],
         destination: Option<mac::Address>,
         send_time: SendTime,
         config: TxConfig,
     ) -> Result<DW1000<SPI, Sending>, Error<SPI>> {
-        let seq = self.next_seq();
+        self.try_send(data, destination, send_time, config)
+            .map_err(|(_, error)| error)
+    }
 
-        let frame = mac::Frame {
-            header: mac::Header {
-                frame_type: mac::FrameType::Data,
-                version: mac::FrameVersion::Ieee802154_2006,
-                auxiliary_security_header: None,
-                ie_present: false,
-                seq_no_suppress: false,
-                frame_pending: false,
-                ack_request: false,
-                pan_id_compress: false,
-                destination,
-                source: Some(self.get_address()?),
-                seq,
-            },
-            content: mac::FrameContent::Data,
-            payload: data,
-            footer: [0; 2],
+    /// Like [`DW1000::send`], but returns the DW1000 along with the error
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn try_send(
+        mut self,
+        data: &[u8],
+        destination: Option<mac::Address>,
+        send_time: SendTime,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, Sending>, (Self, Error<SPI>)> {
+        let frame = match self.data_frame(data, destination, false) {
+            Ok(frame) => frame,
+            Err(error) => return Err((self, error)),
         };
 
-        self.send_raw(
-            |data| {
-                let mut len = 0;
-                let result = data.write_with(
-                    &mut len,
-                    frame,
-                    &mut FrameSerDesContext::no_security(FooterMode::None),
-                );
+        self.send_with_writer(|buffer| write_frame(frame, buffer), send_time, config)
+    }
+
+    /// Send an IEEE 802.15.4 MAC frame and wait for a response
+    ///
+    /// Works like [`DW1000::send`], but instructs the DW1000 to turn on the
+    /// receiver by itself once the frame has been sent. This removes the gap
+    /// between sending and receiving, in which a quick response could be
+    /// missed.
+    ///
+    /// The receiver is turned on `response_delay` after the end of the
+    /// transmission. The delay is in units of 512/499.2 MHz (~1.026 µs) and
+    /// may be at most `0xFFFFF`.
+    ///
+    /// The channel, bitrate, pulse repetition frequency and SFD sequence of
+    /// `tx_config` and `rx_config` must be the same, as the chip can't
+    /// reconfigure itself between sending and receiving.
+    ///
+    /// This method starts the transmission and returns immediately thereafter.
+    /// The returned instance is in the `SendingWithResponse` state, which can
+    /// be used to wait for the transmission to finish and then turns into the
+    /// `SingleBufferReceiving` state.
+    pub fn send_and_receive(
+        mut self,
+        data: &[u8],
+        destination: Option<mac::Address>,
+        send_time: SendTime,
+        tx_config: TxConfig,
+        response_delay: u32,
+        rx_config: RxConfig,
+    ) -> Result<DW1000<SPI, SendingWithResponse>, Error<SPI>> {
+        let frame = self.data_frame(data, destination, false)?;
+
+        self.send_and_receive_with_writer(
+            |buffer| write_frame(frame, buffer),
+            send_time,
+            tx_config,
+            response_delay,
+            rx_config,
+        )
+    }
+
+    /// Send raw bytes and wait for a response
+    ///
+    /// The `writer` closure receives a buffer to write the data to be sent into.
+    ///
+    /// See [`DW1000::send_and_receive`] for the other parameters.
+    pub fn send_raw_and_receive(
+        self,
+        writer: impl FnOnce(&mut [u8]) -> usize,
+        send_time: SendTime,
+        tx_config: TxConfig,
+        response_delay: u32,
+        rx_config: RxConfig,
+    ) -> Result<DW1000<SPI, SendingWithResponse>, Error<SPI>> {
+        self.send_and_receive_with_writer(
+            |buffer| Ok(writer(buffer)),
+            send_time,
+            tx_config,
+            response_delay,
+            rx_config,
+        )
+    }
 
-                if let Err(err) = result {
-                    panic!("Failed to write frame: {:?}", err);
-                }
+    /// Like `send_raw_and_receive`, but with a fallible writer
+    fn send_and_receive_with_writer(
+        mut self,
+        writer: impl FnOnce(&mut [u8]) -> Result<usize, Error<SPI>>,
+        send_time: SendTime,
+        tx_config: TxConfig,
+        response_delay: u32,
+        rx_config: RxConfig,
+    ) -> Result<DW1000<SPI, SendingWithResponse>, Error<SPI>> {
+        if tx_config.channel != rx_config.channel
+            || tx_config.bitrate != rx_config.bitrate
+            || tx_config.pulse_repetition_frequency != rx_config.pulse_repetition_frequency
+            || tx_config.sfd_sequence != rx_config.sfd_sequence
+            || tx_config.phr_mode != rx_config.phr_mode
+        {
+            return Err(Error::InvalidConfiguration);
+        }
 
-                len
+        // W4R_TIM is a 20 bit field
+        if response_delay > 0xF_FFFF {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        self.configure_receiver(&rx_config, false, false)?;
+        self.ll
+            .ack_resp_t()
+            .modify(|_, w| w.w4r_tim(response_delay))?;
+
+        self.prepare_transmission(writer, &send_time, &tx_config)?;
+        self.start_transmission(&send_time, tx_config.append_crc, true)?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: SendingWithResponse {
+                finished: false,
+                config: rx_config,
             },
+        })
+    }
+
+    /// Send an IEEE 802.15.4 MAC frame and wait for its acknowledgement
+    ///
+    /// The `data` argument is wrapped into an IEEE 802.15.4 MAC frame with the
+    /// ack request bit set and sent to `destination`. Afterwards, the receiver
+    /// is turned on automatically to wait for the ACK frame, which is sent by
+    /// the receiving node (for example by a DW1000 with
+    /// [`RxConfig::auto_ack`] enabled).
+    ///
+    /// The ACK is received with settings that match `config`. If no frame is
+    /// received within `ack_timeout` (in units of 512/499.2 MHz, ~1.026 µs)
+    /// after the transmission, waiting for the ACK fails with
+    /// [`Error::FrameWaitTimeout`].
+    ///
+    /// This method starts the transmission and returns immediately thereafter.
+    /// The returned instance is in the `AwaitingAck` state, which can be used
+    /// to wait for the ACK.
+    pub fn send_with_ack(
+        mut self,
+        data: &[u8],
+        destination: mac::Address,
+        send_time: SendTime,
+        config: TxConfig,
+        ack_timeout: u16,
+    ) -> Result<DW1000<SPI, AwaitingAck>, Error<SPI>> {
+        let frame = self.data_frame(data, Some(destination), true)?;
+        let seq = frame.header.seq;
+
+        let rx_config = RxConfig {
+            bitrate: config.bitrate,
+            frame_filtering: Some(FrameFilter {
+                behave_as_coordinator: false,
+                allow_beacon: false,
+                allow_data: false,
+                allow_ack: true,
+                allow_mac_command: false,
+                allow_reserved: false,
+                allow_frame_type_4: false,
+                allow_frame_type_5: false,
+            }),
+            pulse_repetition_frequency: config.pulse_repetition_frequency,
+            expected_preamble_length: config.preamble_length,
+            channel: config.channel,
+            sfd_sequence: config.sfd_sequence,
+            append_crc: true,
+            frame_wait_timeout: Some(ack_timeout),
+            phr_mode: config.phr_mode,
+            ..RxConfig::default()
+        };
+
+        let radio = self.send_and_receive_with_writer(
+            |buffer| write_frame(frame, buffer),
             send_time,
             config,
-        )
+            0,
+            rx_config,
+        )?;
+
+        Ok(DW1000 {
+            ll: radio.ll,
+            seq: radio.seq,
+            state: AwaitingAck {
+                finished: false,
+                transmitted: false,
+                seq,
+                config: rx_config,
+            },
+        })
     }
 
     /// Get the sequence number for the next frame to be sent
@@ -193,9 +422,31 @@ where
         seq
     }
 
+    /// Builds a data frame from this radio's address to `destination`
+    fn data_frame<'p>(
+        &mut self,
+        payload: &'p [u8],
+        destination: Option<mac::Address>,
+        ack_request: bool,
+    ) -> Result<mac::Frame<'p>, Error<SPI>> {
+        let seq = self.next_seq();
+        let source = self.get_address()?;
+
+        Ok(build_data_frame(
+            payload,
+            destination,
+            source,
+            seq,
+            ack_request,
+        ))
+    }
+
     /// Send raw bytes
     ///
-    /// The `writer` closure receives a buffer to write the data to be sent into.
+    /// The `writer` closure receives a buffer to write the data to be sent into,
+    /// and returns the number of bytes written. The buffer leaves room for the
+    /// two-octet CRC, so its length depends on [`TxConfig::phr_mode`]. If the
+    /// returned length doesn't fit, [`Error::FrameTooLong`] is returned.
     ///
     /// This operation can be delayed to aid in distance measurement, by setting
     /// `delayed_time` to `Some(instant)`. If you want to send the frame as soon
@@ -211,141 +462,30 @@ where
     /// is in the `Sending` state, and can be used to wait for the transmission
     /// to finish and check its result.
     pub fn send_raw(
-        mut self,
+        self,
         writer: impl FnOnce(&mut [u8]) -> usize,
         send_time: SendTime,
         config: TxConfig,
     ) -> Result<DW1000<SPI, Sending>, Error<SPI>> {
-        // Clear event counters
-        self.ll.evc_ctrl().write(|w| w.evc_clr(0b1))?;
-        while self.ll.evc_ctrl().read()?.evc_clr() == 0b1 {}
-
-        // (Re-)Enable event counters
-        self.ll.evc_ctrl().write(|w| w.evc_en(0b1))?;
-        while self.ll.evc_ctrl().read()?.evc_en() == 0b1 {}
-
-        // Sometimes, for unknown reasons, the DW1000 gets stuck in RX mode.
-        // Starting the transmitter won't get it to enter TX mode, which means
-        // all subsequent send operations will fail. Let's disable the
-        // transceiver and force the chip into IDLE mode to make sure that
-        // doesn't happen.
-        self.force_idle(false)?;
-
-        match send_time {
-            SendTime::Delayed(time) => {
-                // Put the time into the delay register
-                // By setting this register, the chip knows to delay before transmitting
-                self.ll.dx_time().write(|w| w.value(time.value()))?;
-            }
-            SendTime::OnSync => {
-                self.ll.ec_ctrl().modify(|_, w| w.wait(33).ostsm(1))?;
-            }
-            _ => {}
-        }
-
-        // Prepare transmitter
-        let mut len = 0;
-        self.ll.tx_buffer().write(|w| {
-            len = writer(w.data());
-            w
-        })?;
-        self.ll.tx_fctrl().modify(|_, w| {
-            let tflen = len as u8 + 2;
-            w.tflen(tflen) // data length + two-octet CRC
-                .tfle(0) // no non-standard length extension
-                .txboffs(0) // no offset in TX_BUFFER
-                .txbr(config.bitrate as u8) // configured bitrate
-                .tr(config.ranging_enable as u8) // configured ranging bit
-                .txprf(config.pulse_repetition_frequency as u8) // configured PRF
-                .txpsr(((config.preamble_length as u8) & 0b1100) >> 2) // first two bits of configured preamble length
-                .pe((config.preamble_length as u8) & 0b0011) // last two bits of configured preamble length
-        })?;
-
-        // Set the channel and sfd settings
-        self.ll.chan_ctrl().modify(|_, w| {
-            w.tx_chan(config.channel as u8)
-                .rx_chan(config.channel as u8)
-                .dwsfd(
-                    (config.sfd_sequence == SfdSequence::Decawave
-                        || config.sfd_sequence == SfdSequence::DecawaveAlt)
-                        as u8,
-                )
-                .rxprf(config.pulse_repetition_frequency as u8)
-                .tnssfd(
-                    (config.sfd_sequence == SfdSequence::User
-                        || config.sfd_sequence == SfdSequence::DecawaveAlt)
-                        as u8,
-                )
-                .rnssfd(
-                    (config.sfd_sequence == SfdSequence::User
-                        || config.sfd_sequence == SfdSequence::DecawaveAlt)
-                        as u8,
-                )
-                .tx_pcode(
-                    config
-                        .channel
-                        .get_recommended_preamble_code(config.pulse_repetition_frequency),
-                )
-                .rx_pcode(
-                    config
-                        .channel
-                        .get_recommended_preamble_code(config.pulse_repetition_frequency),
-                )
-        })?;
+        self.send_with_writer(|buffer| Ok(writer(buffer)), send_time, config)
+            .map_err(|(_, error)| error)
+    }
 
-        match config.sfd_sequence {
-            SfdSequence::IEEE => {} // IEEE has predefined sfd lengths and the register has no effect.
-            SfdSequence::Decawave => self.ll.sfd_length().write(|w| w.value(8))?, // This isn't entirely necessary as the Decawave8 settings in chan_ctrl already force it to 8
-            SfdSequence::DecawaveAlt => self.ll.sfd_length().write(|w| w.value(16))?, // Set to 16
-            SfdSequence::User => {} // Users are responsible for setting the lengths themselves
+    /// Like `send_raw`, but with a fallible writer
+    #[allow(clippy::type_complexity)]
+    fn send_with_writer(
+        mut self,
+        writer: impl FnOnce(&mut [u8]) -> Result<usize, Error<SPI>>,
+        send_time: SendTime,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, Sending>, (Self, Error<SPI>)> {
+        if let Err(error) = self
+            .prepare_transmission(writer, &send_time, &config)
+            .and_then(|()| self.start_transmission(&send_time, config.append_crc, false))
+        {
+            return Err((self, error));
         }
 
-        // Tune for the correct channel
-        self.ll
-            .rf_txctrl()
-            .write(|w| w.value(config.channel.get_recommended_rf_txctrl()))?;
-        self.ll
-            .tc_pgdelay()
-            .write(|w| w.value(config.channel.get_recommended_tc_pgdelay()))?;
-        self.ll
-            .fs_pllcfg()
-            .write(|w| w.value(config.channel.get_recommended_fs_pllcfg()))?;
-        self.ll
-            .fs_plltune()
-            .write(|w| w.value(config.channel.get_recommended_fs_plltune()))?;
-
-        // Set the LDE registers
-        self.ll
-            .lde_cfg2()
-            .modify(|_, w| w.value(config.pulse_repetition_frequency.get_recommended_lde_cfg2()))?;
-        self.ll.lde_repc().write(|w| {
-            w.value(
-                config.channel.get_recommended_lde_repc_value(
-                    config.pulse_repetition_frequency,
-                    config.bitrate,
-                ),
-            )
-        })?;
-
-        // Todo: Power control (register 0x1E)
-
-        self.ll.sys_ctrl().modify(|_, w| {
-            // Do we want to suppress crc generation?
-            let w = w.sfcst(!config.append_crc as u8);
-
-            if !matches!(send_time, SendTime::OnSync) {
-                // Start transmission
-                if matches!(send_time, SendTime::Delayed(_)) {
-                    w.txdlys(0b1)
-                } else {
-                    w
-                }
-                .txstrt(0b1)
-            } else {
-                w
-            }
-        })?;
-
         Ok(DW1000 {
             ll: self.ll,
             seq: self.seq,
@@ -367,6 +507,41 @@ where
         self,
         config: RxConfig,
     ) -> Result<DW1000<SPI, SingleBufferReceiving>, Error<SPI>> {
+        self.try_receive(config).map_err(|(_, error)| error)
+    }
+
+    /// Like [`DW1000::receive`], but returns the DW1000 along with the error
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn try_receive(
+        self,
+        config: RxConfig,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, (Self, Error<SPI>)> {
+        self.receive_at(ReceiveTime::Now, config)
+    }
+
+    /// Attempt to receive a single IEEE 802.15.4 MAC frame, starting at the
+    /// given time
+    ///
+    /// Like [`DW1000::receive`], but keeps the receiver off until `time`,
+    /// which saves power while waiting for a reply that is known not to
+    /// arrive earlier. If that time has already passed when the receiver is
+    /// set up, waiting for a message will fail with
+    /// [`Error::DelayedReceiveTooLate`].
+    pub fn receive_delayed(
+        self,
+        time: Instant,
+        config: RxConfig,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, Error<SPI>> {
+        self.receive_at(ReceiveTime::Delayed(time), config)
+            .map_err(|(_, error)| error)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn receive_at(
+        self,
+        receive_time: ReceiveTime,
+        config: RxConfig,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, (Self, Error<SPI>)> {
         let mut rx_radio = DW1000 {
             ll: self.ll,
             seq: self.seq,
@@ -377,7 +552,14 @@ where
         };
 
         // Start rx'ing
-        rx_radio.start_receiving(config)?;
+        if let Err(error) = rx_radio.start_receiving(receive_time, config) {
+            let radio = DW1000 {
+                ll: rx_radio.ll,
+                seq: rx_radio.seq,
+                state: Ready,
+            };
+            return Err((radio, error));
+        }
 
         // Return the double buffer state
         Ok(rx_radio)
@@ -400,6 +582,28 @@ where
     pub fn receive_auto_double_buffered(
         self,
         config: RxConfig,
+    ) -> Result<DW1000<SPI, AutoDoubleBufferReceiving>, Error<SPI>> {
+        self.receive_auto_double_buffered_at(ReceiveTime::Now, config)
+    }
+
+    /// Attempt to receive many IEEE 802.15.4 MAC frames, starting at the
+    /// given time
+    ///
+    /// Like [`DW1000::receive_auto_double_buffered`], but keeps the receiver
+    /// off until `time`. This only affects the first message, as the receiver
+    /// is re-enabled immediately after that.
+    pub fn receive_auto_double_buffered_delayed(
+        self,
+        time: Instant,
+        config: RxConfig,
+    ) -> Result<DW1000<SPI, AutoDoubleBufferReceiving>, Error<SPI>> {
+        self.receive_auto_double_buffered_at(ReceiveTime::Delayed(time), config)
+    }
+
+    fn receive_auto_double_buffered_at(
+        self,
+        receive_time: ReceiveTime,
+        config: RxConfig,
     ) -> Result<DW1000<SPI, AutoDoubleBufferReceiving>, Error<SPI>> {
         let mut rx_radio = DW1000 {
             ll: self.ll,
@@ -411,7 +615,7 @@ where
         };
 
         // Start rx'ing
-        rx_radio.start_receiving(config)?;
+        rx_radio.start_receiving(receive_time, config)?;
 
         // Return the double buffer state
         Ok(rx_radio)
@@ -491,80 +695,54 @@ where
 
         Ok(())
     }
+}
 
-    /// Puts the dw1000 into sleep mode.
-    ///
-    /// - `irq_on_wakeup`: When set to true, the IRQ pin will be asserted when the radio wakes up
-    /// - `sleep_duration`: When `None`, the radio will not wake up by itself and go into the deep sleep mode.
-    ///   When `Some`, then the radio will wake itself up after the given time. Every tick is ~431ms, but there may
-    ///   be a significant deviation from this due to the chip's manufacturing process.
-    ///
-    /// *Note: The SPI speed may be at most 3 Mhz when calling this function.*
-    pub fn enter_sleep(
-        mut self,
-        irq_on_wakeup: bool,
-        sleep_duration: Option<u16>,
-    ) -> Result<DW1000<SPI, Sleeping>, Error<SPI>> {
-        // Set the sleep timer
-        if let Some(sd) = sleep_duration {
-            self.ll.pmsc_ctrl0().modify(|_, w| {
-                w
-                    // Force the 19.2Mhz clock
-                    .sysclks(0b01)
-            })?;
-
-            // Disable the sleep counter
-            self.ll
-                .aon_cfg1()
-                .write(|w| w.sleep_cen(0).smxx(0).lposc_cal(0))?;
-            // Set the counter
-            self.ll.aon_cfg0().write(|w| w.sleep_tim(sd))?;
-            // Enable the sleep counter
-            self.ll.aon_cfg1().write(|w| w.sleep_cen(1).lposc_cal(1))?;
-            // Upload array
-            self.ll.aon_ctrl().write(|w| w.upl_cfg(1))?;
-            self.ll.aon_ctrl().write(|w| w.upl_cfg(0))?;
-
-            self.ll.pmsc_ctrl0().modify(|_, w| {
-                w
-                    // Auto clock
-                    .sysclks(0b00)
-            })?;
-        }
-
-        // Save the settings that the
-        let tx_antenna_delay = self.get_tx_antenna_delay()?;
-
-        // Setup the interrupt.
-        if irq_on_wakeup {
-            self.ll
-                .sys_mask()
-                .modify(|_, w| w.mslp2init(1).mcplock(1))?;
-        }
-
-        // Does the chip have the ldo tune calibrated?
-        let lldo = self.read_otp(0x004)? != 0;
-
-        // Setup everything that needs to be stored in AON
-        self.ll
-            .aon_wcfg()
-            .modify(|_, w| w.onw_ldc(1).onw_llde(1).onw_lldo(lldo as u8).onw_l64p(1))?;
-
-        // Setup the wakeup sources.
-        self.ll.aon_cfg0().modify(|_, w| {
-            w.wake_spi(1)
-                .wake_cnt(sleep_duration.is_some() as u8)
-                .sleep_en(1)
-        })?;
-
-        // Upload always on array configuration and enter sleep
-        self.ll.aon_ctrl().write(|w| w)?;
-        self.ll.aon_ctrl().write(|w| w.save(1))?;
+/// Builds a data frame from `source` to `destination`
+pub(super) fn build_data_frame<'p>(
+    payload: &'p [u8],
+    destination: Option<mac::Address>,
+    source: mac::Address,
+    seq: u8,
+    ack_request: bool,
+) -> mac::Frame<'p> {
+    mac::Frame {
+        header: mac::Header {
+            frame_type: mac::FrameType::Data,
+            version: mac::FrameVersion::Ieee802154_2006,
+            auxiliary_security_header: None,
+            ie_present: false,
+            seq_no_suppress: false,
+            frame_pending: false,
+            ack_request,
+            pan_id_compress: false,
+            destination,
+            source: Some(source),
+            seq,
+        },
+        content: mac::FrameContent::Data,
+        payload,
+        footer: [0; 2],
+    }
+}
 
-        Ok(DW1000 {
-            ll: self.ll,
-            seq: self.seq,
-            state: Sleeping { tx_antenna_delay },
-        })
+/// Serializes `frame` into `buffer`, returning the number of bytes written
+pub(super) fn write_frame<SPI>(frame: mac::Frame, buffer: &mut [u8]) -> Result<usize, Error<SPI>>
+where
+    SPI: ErrorType,
+{
+    let mut len = 0;
+    let result = buffer.write_with(
+        &mut len,
+        frame,
+        &mut FrameSerDesContext::no_security(FooterMode::None),
+    );
+
+    match result {
+        Ok(()) => Ok(len),
+        // The buffer handed to the writer leaves room for the CRC
+        Err(byte::Error::Incomplete) => Err(Error::FrameTooLong {
+            max_len: buffer.len() + 2,
+        }),
+        Err(err) => Err(Error::Frame(err)),
     }
 }
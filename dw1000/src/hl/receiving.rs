@@ -1,16 +1,15 @@
 use crate::{
-    configs::{BitRate, SfdSequence},
-    mac,
+    configs::{BitRate, PhrMode},
+    ll, mac,
     time::Instant,
     Error, Ready, RxConfig, DW1000,
 };
 use byte::BytesExt as _;
-use core::convert::TryInto;
-use embedded_hal::spi::SpiDevice;
+use embedded_hal::spi::{ErrorType, SpiDevice};
 use fixed::traits::LossyInto;
 use ieee802154::mac::FooterMode;
 
-use super::{AutoDoubleBufferReceiving, Receiving};
+use super::{sequences, AutoDoubleBufferReceiving, AwaitingAck, Awake, ReceiveTime, Receiving};
 
 /// An incoming message
 #[derive(Debug)]
@@ -56,177 +55,176 @@ pub struct RxQuality {
     pub rssi: f32,
 }
 
-impl<SPI, RECEIVING> DW1000<SPI, RECEIVING>
+/// Returns the length of the received frame, as indicated by RX_FINFO
+///
+/// In the extended PHR mode, the length is extended by the RXFLE field.
+pub(super) fn frame_len(rx_finfo: &ll::rx_finfo::R, phr_mode: PhrMode) -> usize {
+    match phr_mode {
+        PhrMode::Standard => rx_finfo.rxflen() as usize,
+        PhrMode::Extended => rx_finfo.rxflen() as usize | (rx_finfo.rxfle() as usize) << 7,
+    }
+}
+
+/// Returns the receive error indicated by SYS_STATUS, if any
+pub(super) fn rx_error<SPI>(sys_status: &ll::sys_status::R) -> Option<Error<SPI>>
+where
+    SPI: ErrorType,
+{
+    if sys_status.rxfce() == 0b1 {
+        return Some(Error::Fcs);
+    }
+    if sys_status.rxphe() == 0b1 {
+        return Some(Error::Phy);
+    }
+    if sys_status.rxrfsl() == 0b1 {
+        return Some(Error::ReedSolomon);
+    }
+    if sys_status.rxrfto() == 0b1 {
+        return Some(Error::FrameWaitTimeout);
+    }
+    if sys_status.rxovrr() == 0b1 {
+        return Some(Error::Overrun);
+    }
+    if sys_status.rxpto() == 0b1 {
+        return Some(Error::PreambleDetectionTimeout);
+    }
+    if sys_status.rxsfdto() == 0b1 {
+        return Some(Error::SfdTimeout);
+    }
+    if sys_status.affrej() == 0b1 {
+        return Some(Error::FrameFilteringRejection);
+    }
+    // Some error flags that sound like valid errors aren't checked here,
+    // because experience has shown that they seem to occur spuriously
+    // without preventing a good frame from being received. Those are:
+    // - LDEERR: Leading Edge Detection Processing Error
+    // - RXPREJ: Receiver Preamble Rejection
+
+    None
+}
+
+impl<SPI, State> DW1000<SPI, State>
 where
     SPI: SpiDevice,
-    RECEIVING: Receiving,
+    State: Awake,
 {
-    pub(super) fn start_receiving(&mut self, config: RxConfig) -> Result<(), Error<SPI>> {
-        // Really weird thing about double buffering I can't find anything about.
-        // When a message is received in double buffer mode that should be filtered out,
-        // the radio gives a really short fake interrupt.
-        // This messes up all the logic, so unless a solution can be found we simply don't support it.
-        if RECEIVING::DOUBLE_BUFFERED && config.frame_filtering {
-            return Err(Error::RxConfigFrameFilteringUnsupported);
-        }
+    /// Applies the receive configuration, without enabling the receiver
+    ///
+    /// This is shared between the receive states and `SendingWithResponse`,
+    /// where the receiver is enabled by the chip itself after transmitting.
+    pub(super) fn configure_receiver(
+        &mut self,
+        config: &RxConfig,
+        double_buffered: bool,
+        auto_rx_reenable: bool,
+    ) -> Result<(), Error<SPI>> {
+        sequences::configure_receiver!(blocking, self, config, double_buffered, auto_rx_reenable)
+    }
 
-        // For unknown reasons, the DW1000 gets stuck in RX mode without ever
-        // receiving anything, after receiving one good frame. Reset the
-        // receiver to make sure its in a valid state before attempting to
-        // receive anything.
-        self.ll.pmsc_ctrl0().modify(
-            |_, w| w.softreset(0b1110), // reset receiver
-        )?;
-        self.ll.pmsc_ctrl0().modify(
-            |_, w| w.softreset(0b1111), // clear reset
-        )?;
+    /// Enables the receiver, either right away or at the given time
+    pub(super) fn enable_receiver(&mut self, receive_time: ReceiveTime) -> Result<(), Error<SPI>> {
+        sequences::enable_receiver!(blocking, self, receive_time)
+    }
+}
 
-        // We're already resetting the receiver in the previous step, and that's
-        // good enough to make my example program that's both sending and
-        // receiving work very reliably over many hours (that's not to say it
-        // becomes unreliable after those hours, that's just when my test
-        // stopped). However, I've seen problems with an example program that
-        // only received, never sent, data. That got itself into some weird
-        // state where it couldn't receive anymore.
-        // I suspect that's because that example didn't have the following line
-        // of code, while the send/receive example had that line of code, being
-        // called from `send`.
-        // While I haven't, as of this writing, run any hours-long tests to
-        // confirm this does indeed fix the receive-only example, it seems
-        // (based on my eyeball-only measurements) that the RX/TX example is
-        // dropping fewer frames now.
-        self.force_idle(false)?;
-
-        self.ll.sys_cfg().modify(|_, w| {
-            w.ffen(config.frame_filtering as u8) // enable or disable frame filtering
-                .ffab(0b1) // receive beacon frames
-                .ffad(0b1) // receive data frames
-                .ffaa(0b1) // receive acknowledgement frames
-                .ffam(0b1) // receive MAC command frames
-                // Set the double buffering and auto re-enable
-                .dis_drxb(!RECEIVING::DOUBLE_BUFFERED as u8)
-                .rxautr(RECEIVING::AUTO_RX_REENABLE as u8)
-                // Set whether the receiver should look for 110kbps or 850/6800kbps messages
-                .rxm110k((config.bitrate == BitRate::Kbps110) as u8)
-        })?;
-
-        // Set PLLLDT bit in EC_CTRL. According to the documentation of the
-        // CLKPLL_LL bit in SYS_STATUS, this bit needs to be set to ensure the
-        // reliable operation of the CLKPLL_LL bit. Since I've seen that bit
-        // being set, I want to make sure I'm not just seeing crap.
-        self.ll.ec_ctrl().modify(|_, w| w.pllldt(0b1))?;
-
-        // Now that PLLLDT is set, clear all bits in SYS_STATUS that depend on
-        // it for reliable operation. After that is done, these bits should work
-        // reliably.
-        self.ll
-            .sys_status()
-            .write(|w| w.cplock(0b1).clkpll_ll(0b1))?;
-
-        // Apply the config
-        self.ll.chan_ctrl().modify(|_, w| {
-            w.tx_chan(config.channel as u8)
-                .rx_chan(config.channel as u8)
-                .dwsfd(
-                    (config.sfd_sequence == SfdSequence::Decawave
-                        || config.sfd_sequence == SfdSequence::DecawaveAlt)
-                        as u8,
-                )
-                .rxprf(config.pulse_repetition_frequency as u8)
-                .tnssfd(
-                    (config.sfd_sequence == SfdSequence::User
-                        || config.sfd_sequence == SfdSequence::DecawaveAlt)
-                        as u8,
-                )
-                .rnssfd(
-                    (config.sfd_sequence == SfdSequence::User
-                        || config.sfd_sequence == SfdSequence::DecawaveAlt)
-                        as u8,
-                )
-                .tx_pcode(
-                    config
-                        .channel
-                        .get_recommended_preamble_code(config.pulse_repetition_frequency),
-                )
-                .rx_pcode(
-                    config
-                        .channel
-                        .get_recommended_preamble_code(config.pulse_repetition_frequency),
-                )
-        })?;
-
-        match config.sfd_sequence {
-            SfdSequence::IEEE => {} // IEEE has predefined sfd lengths and the register has no effect.
-            SfdSequence::Decawave => self.ll.sfd_length().write(|w| w.value(8))?, // This isn't entirely necessary as the Decawave8 settings in chan_ctrl already force it to 8
-            SfdSequence::DecawaveAlt => self.ll.sfd_length().write(|w| w.value(16))?, // Set to 16
-            SfdSequence::User => {} // Users are responsible for setting the lengths themselves
+/// The diagnostic values of a received message
+///
+/// These are the raw values the DW1000 reports about the received message,
+/// together with the power levels that are estimated from them.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RxDiagnostics {
+    /// First path amplitude point 1
+    pub fp_ampl1: u16,
+    /// First path amplitude point 2
+    pub fp_ampl2: u16,
+    /// First path amplitude point 3
+    pub fp_ampl3: u16,
+    /// Standard deviation of the noise
+    pub std_noise: u16,
+    /// Channel impulse response power (maximum growth CIR)
+    pub cir_pwr: u16,
+    /// Preamble accumulation count
+    pub rxpacc: u16,
+    /// Preamble accumulation count, not saturated
+    pub rxpacc_nosat: u16,
+    /// The first path index, with a fractional part
+    pub first_path_index: f32,
+    /// The estimated first path power level in dBm
+    pub first_path_power: f32,
+    /// The estimated receive power level in dBm
+    pub rx_power: f32,
+    /// The receive power level minus the first path power level in dB
+    ///
+    /// As a rule of thumb, a difference of less than 6 dB indicates that the
+    /// channel is likely line-of-sight, while a difference of more than 10 dB
+    /// indicates that it is likely non-line-of-sight.
+    pub power_difference: f32,
+}
+
+/// A single sample of the channel impulse response (CIR)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CirSample {
+    /// The real part of the sample
+    pub real: i16,
+    /// The imaginary part of the sample
+    pub imag: i16,
+}
+
+impl CirSample {
+    /// Decodes a sample from the accumulator memory
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        CirSample {
+            real: i16::from_le_bytes([bytes[0], bytes[1]]),
+            imag: i16::from_le_bytes([bytes[2], bytes[3]]),
         }
+    }
 
-        // Set general tuning
-        self.ll.drx_tune0b().write(|w| {
-            w.value(
-                config
-                    .bitrate
-                    .get_recommended_drx_tune0b(config.sfd_sequence),
-            )
-        })?;
-        self.ll.drx_tune1a().write(|w| {
-            w.value(
-                config
-                    .pulse_repetition_frequency
-                    .get_recommended_drx_tune1a(),
-            )
-        })?;
-        let drx_tune1b = config
-            .expected_preamble_length
-            .get_recommended_drx_tune1b(config.bitrate)?;
-        self.ll.drx_tune1b().write(|w| w.value(drx_tune1b))?;
-        let drx_tune2 = config
-            .pulse_repetition_frequency
-            .get_recommended_drx_tune2(
-                config.expected_preamble_length.get_recommended_pac_size(),
-            )?;
-        self.ll.drx_tune2().write(|w| w.value(drx_tune2))?;
-        self.ll
-            .drx_tune4h()
-            .write(|w| w.value(config.expected_preamble_length.get_recommended_dxr_tune4h()))?;
+    /// The squared magnitude of the sample
+    pub fn magnitude_squared(&self) -> u32 {
+        let real = self.real as i32;
+        let imag = self.imag as i32;
+        (real * real + imag * imag) as u32
+    }
 
-        // Set channel tuning
-        self.ll
-            .rf_rxctrlh()
-            .write(|w| w.value(config.channel.get_recommended_rf_rxctrlh()))?;
-        self.ll
-            .fs_pllcfg()
-            .write(|w| w.value(config.channel.get_recommended_fs_pllcfg()))?;
-        self.ll
-            .fs_plltune()
-            .write(|w| w.value(config.channel.get_recommended_fs_plltune()))?;
+    /// The magnitude of the sample
+    pub fn magnitude(&self) -> f32 {
+        #[allow(unused_imports)]
+        use micromath::F32Ext;
 
-        // Set the LDE registers
-        self.ll
-            .lde_cfg2()
-            .write(|w| w.value(config.pulse_repetition_frequency.get_recommended_lde_cfg2()))?;
-        self.ll.lde_repc().write(|w| {
-            w.value(
-                config.channel.get_recommended_lde_repc_value(
-                    config.pulse_repetition_frequency,
-                    config.bitrate,
-                ),
-            )
-        })?;
-
-        // Check if the rx buffer pointer is correct
-        let status = self.ll.sys_status().read()?;
-        if status.hsrbp() != status.icrbp() {
-            // The RX Buffer Pointer of the host and the ic side don't point to the same one.
-            // We need to switch over
-            self.ll.sys_ctrl().modify(|_, w| w.hrbpt(1))?;
-        }
+        (self.magnitude_squared() as f32).sqrt()
+    }
+}
 
-        // Start receiving
-        self.ll.sys_ctrl().modify(|_, w| w.rxenab(0b1))?;
+/// A window of the channel impulse response (CIR)
+#[derive(Debug)]
+pub struct Cir<'b> {
+    /// The index of the first sample in the accumulator memory
+    pub start_index: u16,
+    /// The first path index, as reported by the leading edge detection
+    ///
+    /// This is an index into the accumulator memory with a fractional part.
+    /// Subtract `start_index` to get the position within `samples`.
+    pub first_path_index: f32,
+    /// The samples, starting at `start_index`
+    pub samples: &'b [CirSample],
+}
 
-        Ok(())
+impl<SPI, RECEIVING> DW1000<SPI, RECEIVING>
+where
+    SPI: SpiDevice,
+    RECEIVING: Receiving,
+{
+    pub(super) fn start_receiving(
+        &mut self,
+        receive_time: ReceiveTime,
+        config: RxConfig,
+    ) -> Result<(), Error<SPI>> {
+        self.configure_receiver(
+            &config,
+            RECEIVING::DOUBLE_BUFFERED,
+            RECEIVING::AUTO_RX_REENABLE,
+        )?;
+        self.enable_receiver(receive_time)
     }
 
     /// Wait for receive operation to finish
@@ -286,38 +284,19 @@ where
             .read()
             .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?;
 
+        // Check Half Period Delay Warning. If this is a delayed receive, this
+        // indicates that the receiver was enabled too late, and the chip would
+        // only turn it on after the system time has wrapped around.
+        if sys_status.hpdwarn() == 0b1 {
+            return Err(nb::Error::Other(Error::DelayedReceiveTooLate));
+        }
+
         // Is a frame ready?
         if sys_status.rxdfr() == 0b0 {
             // No frame ready. Check for errors.
-            if sys_status.rxfce() == 0b1 {
-                return Err(nb::Error::Other(Error::Fcs));
-            }
-            if sys_status.rxphe() == 0b1 {
-                return Err(nb::Error::Other(Error::Phy));
-            }
-            if sys_status.rxrfsl() == 0b1 {
-                return Err(nb::Error::Other(Error::ReedSolomon));
-            }
-            if sys_status.rxrfto() == 0b1 {
-                return Err(nb::Error::Other(Error::FrameWaitTimeout));
-            }
-            if sys_status.rxovrr() == 0b1 {
-                return Err(nb::Error::Other(Error::Overrun));
-            }
-            if sys_status.rxpto() == 0b1 {
-                return Err(nb::Error::Other(Error::PreambleDetectionTimeout));
-            }
-            if sys_status.rxsfdto() == 0b1 {
-                return Err(nb::Error::Other(Error::SfdTimeout));
-            }
-            if sys_status.affrej() == 0b1 {
-                return Err(nb::Error::Other(Error::FrameFilteringRejection));
+            if let Some(error) = rx_error(&sys_status) {
+                return Err(nb::Error::Other(error));
             }
-            // Some error flags that sound like valid errors aren't checked here,
-            // because experience has shown that they seem to occur spuriously
-            // without preventing a good frame from being received. Those are:
-            // - LDEERR: Leading Edge Detection Processing Error
-            // - RXPREJ: Receiver Preamble Rejection
 
             // No errors detected. That must mean the frame is just not ready
             // yet.
@@ -349,13 +328,7 @@ where
             .rx_finfo()
             .read()
             .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?;
-        let rx_buffer = self
-            .ll()
-            .rx_buffer()
-            .read()
-            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?;
-
-        let len = rx_finfo.rxflen() as usize;
+        let len = frame_len(&rx_finfo, self.state.get_rx_config().phr_mode);
 
         if buffer.len() < len {
             return Err(nb::Error::Other(Error::BufferTooSmall {
@@ -363,7 +336,9 @@ where
             }));
         }
 
-        buffer[..len].copy_from_slice(&rx_buffer.data()[..len]);
+        self.ll()
+            .read_rx_data(0, &mut buffer[..len])
+            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?;
 
         let bytes = &buffer[..len];
 
@@ -430,9 +405,6 @@ where
     }
 
     fn calculate_luep(&mut self) -> Result<f32, Error<SPI>> {
-        #[allow(unused_imports)]
-        use micromath::F32Ext;
-
         let rx_time_register = self.ll().rx_time().read()?;
         let rx_fqual_register = self.ll().rx_fqual().read()?;
         let lde_cfg1_register = self.ll().lde_cfg1().read()?;
@@ -461,11 +433,8 @@ where
         let mut amplitudes = [0.0; WINDOW_SIZE];
         let mut peak_count = 0;
         for index in 0..WINDOW_SIZE {
-            let real = u16::from_le_bytes(cir[index * 4..index * 4 + 2].try_into().unwrap()) as f32;
-            let imag =
-                u16::from_le_bytes(cir[index * 4 + 2..index * 4 + 4].try_into().unwrap()) as f32;
-
-            amplitudes[index] = (real * real + imag * imag).sqrt();
+            amplitudes[index] =
+                CirSample::from_le_bytes(&cir[index * 4..index * 4 + 4]).magnitude();
 
             if index >= 2 && amplitudes[index - 1] > new_low_threshold as f32 {
                 let previous_difference = amplitudes[index - 1] - amplitudes[index - 2];
@@ -516,34 +485,95 @@ where
     ///
     /// Algorithm was taken from `4.7.2 Estimating the receive signal power` of the user manual.
     fn calculate_rssi(&mut self) -> Result<f32, Error<SPI>> {
+        let c = self.ll.rx_fqual().read()?.cir_pwr() as f32;
+        let rssi = self.power_level(c * (1 << 17) as f32)?;
+
+        if rssi.is_finite() {
+            Ok(rssi)
+        } else {
+            Err(Error::BadRssiCalculation)
+        }
+    }
+
+    /// Converts an energy reading to a power level in dBm
+    ///
+    /// This is the formula shared by the receive power and first path power
+    /// estimations in section 4.7 of the user manual, where `energy` is
+    /// `C * 2^17` or `F1^2 + F2^2 + F3^2` respectively.
+    fn power_level(&mut self, energy: f32) -> Result<f32, Error<SPI>> {
         #[allow(unused_imports)]
         use micromath::F32Ext;
 
-        let c = self.ll.rx_fqual().read()?.cir_pwr() as f32;
-        let a = match self.state.get_rx_config().pulse_repetition_frequency {
+        let a = self.power_constant();
+        let n = self.corrected_preamble_count()?;
+
+        Ok(10.0 * (energy / (n * n)).log10() - a)
+    }
+
+    /// The PRF dependent constant `A` of the power estimations in the user manual
+    fn power_constant(&self) -> f32 {
+        match self.state.get_rx_config().pulse_repetition_frequency {
             crate::configs::PulseRepetitionFrequency::Mhz16 => 113.77,
             crate::configs::PulseRepetitionFrequency::Mhz64 => 121.74,
-        };
+        }
+    }
 
+    /// The preamble accumulation count `N`, corrected for the SFD
+    fn corrected_preamble_count(&mut self) -> Result<f32, Error<SPI>> {
         let data_rate = self.state.get_rx_config().bitrate;
         let sfd_sequence = self.state.get_rx_config().sfd_sequence;
 
         let rxpacc = self.ll.rx_finfo().read()?.rxpacc();
         let rxpacc_nosat = self.ll.rxpacc_nosat().read()?.value();
 
-        let n = if rxpacc == rxpacc_nosat {
+        Ok(if rxpacc == rxpacc_nosat {
             rxpacc as f32 + sfd_sequence.get_rxpacc_adjustment(data_rate) as f32
         } else {
             rxpacc as f32
-        };
-
-        let rssi = 10.0 * ((c * (1 << 17) as f32) / (n * n)).log10() - a;
+        })
+    }
 
-        if rssi.is_finite() {
-            Ok(rssi)
-        } else {
-            Err(Error::BadRssiCalculation)
+    /// Reads the diagnostic values of the received message.
+    ///
+    /// The first path and receive power levels are estimated as described in
+    /// section 4.7 of the user manual.
+    ///
+    /// This must be called after the [`DW1000::wait_receive`] function has
+    /// successfully returned, otherwise [`Error::RxNotFinished`] is returned.
+    pub fn read_rx_diagnostics(&mut self) -> Result<RxDiagnostics, Error<SPI>> {
+        if !self.state.is_finished() {
+            return Err(Error::RxNotFinished);
         }
+
+        let rx_time = self.ll.rx_time().read()?;
+        let rx_fqual = self.ll.rx_fqual().read()?;
+        let rxpacc = self.ll.rx_finfo().read()?.rxpacc();
+        let rxpacc_nosat = self.ll.rxpacc_nosat().read()?.value();
+
+        let first_path_index: f32 =
+            fixed::types::U10F6::from_le_bytes(rx_time.fp_index().to_le_bytes()).lossy_into();
+
+        let f1 = rx_time.fp_ampl1() as f32;
+        let f2 = rx_fqual.fp_ampl2() as f32;
+        let f3 = rx_fqual.fp_ampl3() as f32;
+        let first_path_power = self.power_level(f1 * f1 + f2 * f2 + f3 * f3)?;
+
+        let c = rx_fqual.cir_pwr() as f32;
+        let rx_power = self.power_level(c * (1 << 17) as f32)?;
+
+        Ok(RxDiagnostics {
+            fp_ampl1: rx_time.fp_ampl1(),
+            fp_ampl2: rx_fqual.fp_ampl2(),
+            fp_ampl3: rx_fqual.fp_ampl3(),
+            std_noise: rx_fqual.std_noise(),
+            cir_pwr: rx_fqual.cir_pwr(),
+            rxpacc,
+            rxpacc_nosat,
+            first_path_index,
+            first_path_power,
+            rx_power,
+            power_difference: rx_power - first_path_power,
+        })
     }
 
     /// Reads the quality of the received message.
@@ -573,6 +603,150 @@ where
         })
     }
 
+    /// Reads a window of the channel impulse response (CIR)
+    ///
+    /// The window starts `samples_before_first_path` samples before the first
+    /// path index and is as long as `buffer`, but never extends past the end
+    /// of the accumulator (992 samples at 16 MHz PRF, 1016 samples at 64 MHz
+    /// PRF).
+    ///
+    /// This must be called after the [`DW1000::wait_receive`] function has
+    /// successfully returned, otherwise [`Error::RxNotFinished`] is returned.
+    pub fn read_cir<'b>(
+        &mut self,
+        samples_before_first_path: u16,
+        buffer: &'b mut [CirSample],
+    ) -> Result<Cir<'b>, Error<SPI>> {
+        if !self.state.is_finished() {
+            return Err(Error::RxNotFinished);
+        }
+
+        let accumulator_len = match self.state.get_rx_config().pulse_repetition_frequency {
+            crate::configs::PulseRepetitionFrequency::Mhz16 => 992,
+            crate::configs::PulseRepetitionFrequency::Mhz64 => 1016,
+        };
+
+        let first_path_index: f32 =
+            fixed::types::U10F6::from_le_bytes(self.ll.rx_time().read()?.fp_index().to_le_bytes())
+                .lossy_into();
+
+        let start_index = (first_path_index as u16)
+            .saturating_sub(samples_before_first_path)
+            .min(accumulator_len);
+        let len = buffer.len().min((accumulator_len - start_index) as usize);
+
+        // The accumulator memory can only be read with its clocks forced on.
+        let saved_pmsc_ctrl0 = self.ll.pmsc_ctrl0().read()?.raw_value();
+        self.ll
+            .pmsc_ctrl0()
+            .modify(|_, w| w.rxclks(0b10).face(0b1).amce(0b1))?;
+
+        // Read in chunks, so we don't need a huge buffer on the stack.
+        // `ll::DW1000::cir` needs 4 bytes of room for the protocol overhead.
+        const CHUNK_LEN: usize = 32;
+        let mut bytes = [0; CHUNK_LEN * 4 + 4];
+
+        let mut result = Ok(());
+        for (chunk_index, chunk) in buffer[..len].chunks_mut(CHUNK_LEN).enumerate() {
+            let index = start_index + (chunk_index * CHUNK_LEN) as u16;
+            let bytes = &mut bytes[..chunk.len() * 4 + 4];
+
+            match self.ll.cir(index * 4, bytes) {
+                Ok(cir) => {
+                    for (sample, bytes) in chunk.iter_mut().zip(cir.chunks_exact(4)) {
+                        *sample = CirSample::from_le_bytes(bytes);
+                    }
+                }
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+
+        // Restore the clocks, even if reading failed
+        self.ll
+            .pmsc_ctrl0()
+            .write(|w| w.raw_value(saved_pmsc_ctrl0))?;
+        result?;
+
+        Ok(Cir {
+            start_index,
+            first_path_index,
+            samples: &buffer[..len],
+        })
+    }
+
+    /// Estimates the clock offset between the remote transmitter and the
+    /// local receiver in ppm
+    ///
+    /// A positive value means the remote clock is running faster than the
+    /// local clock.
+    ///
+    /// At 850 kbps and 6.8 Mbps this uses the carrier integrator (see
+    /// [`DW1000::read_carrier_integrator_ppm`]), at 110 kbps the time tracking
+    /// offset (see [`DW1000::read_time_tracking_offset_ppm`]).
+    ///
+    /// This must be called after the [`DW1000::wait_receive`] function has
+    /// successfully returned, otherwise [`Error::RxNotFinished`] is returned.
+    pub fn read_clock_offset_ppm(&mut self) -> Result<f32, Error<SPI>> {
+        match self.state.get_rx_config().bitrate {
+            BitRate::Kbps110 => self.read_time_tracking_offset_ppm(),
+            BitRate::Kbps850 | BitRate::Kbps6800 => self.read_carrier_integrator_ppm(),
+        }
+    }
+
+    /// Estimates the clock offset from the carrier recovery integrator in ppm
+    ///
+    /// A positive value means the remote clock is running faster than the
+    /// local clock.
+    ///
+    /// This must be called after the [`DW1000::wait_receive`] function has
+    /// successfully returned, otherwise [`Error::RxNotFinished`] is returned.
+    pub fn read_carrier_integrator_ppm(&mut self) -> Result<f32, Error<SPI>> {
+        if !self.state.is_finished() {
+            return Err(Error::RxNotFinished);
+        }
+
+        let config = *self.state.get_rx_config();
+
+        // DRX_CAR_INT is a 21 bit signed value. Shift it to the top of the
+        // `i32` and back to sign-extend it.
+        let carrier_integrator = ((self.ll.dxr_car_int().read()?.value() << 11) as i32) >> 11;
+
+        // Conversion factors are taken from the DRX_CAR_INT register
+        // description in the user manual.
+        let hertz_per_unit = match config.bitrate {
+            BitRate::Kbps110 => 998.4e6 / 2.0 / 8192.0 / 131072.0,
+            BitRate::Kbps850 | BitRate::Kbps6800 => 998.4e6 / 2.0 / 1024.0 / 131072.0,
+        };
+        let offset_hz = carrier_integrator as f32 * hertz_per_unit;
+
+        // A positive carrier offset means the local clock is faster, hence
+        // the negation.
+        Ok(offset_hz * -1.0e6 / config.channel.get_center_frequency_hz())
+    }
+
+    /// Estimates the clock offset from the receiver time tracking in ppm
+    ///
+    /// A positive value means the remote clock is running faster than the
+    /// local clock.
+    ///
+    /// This must be called after the [`DW1000::wait_receive`] function has
+    /// successfully returned, otherwise [`Error::RxNotFinished`] is returned.
+    pub fn read_time_tracking_offset_ppm(&mut self) -> Result<f32, Error<SPI>> {
+        if !self.state.is_finished() {
+            return Err(Error::RxNotFinished);
+        }
+
+        // RXTOFS is a 19 bit signed value. Shift it to the top of the `i32`
+        // and back to sign-extend it.
+        let offset = ((self.ll.rx_ttcko().read()?.rxtofs() << 13) as i32) >> 13;
+        let interval = self.ll.rx_ttcki().read()?.value();
+
+        Ok(offset as f32 / interval as f32 * 1.0e6)
+    }
+
     /// Gets the external sync values from the registers.
     ///
     /// The tuple contains (cycles_since_sync, nanos_until_tick, raw_timestamp).
@@ -631,3 +805,67 @@ where
         }
     }
 }
+
+impl<SPI> DW1000<SPI, AwaitingAck>
+where
+    SPI: SpiDevice,
+{
+    /// Wait for the acknowledgement of the sent frame
+    ///
+    /// This method returns an `nb::Result` to indicate whether the ACK has
+    /// been received, or whether the operation is still ongoing. It can be
+    /// used like [`DW1000::wait_receive`].
+    ///
+    /// Frames that are not the ACK for the sent frame are ignored, and the
+    /// receiver is turned on again to wait for the ACK. This includes frames
+    /// that are rejected by the frame filter, like data or beacon frames. If
+    /// the ACK doesn't arrive in time, this fails with
+    /// [`Error::FrameWaitTimeout`].
+    ///
+    /// Please note that turning on the receiver again restarts the frame wait
+    /// timeout. On a busy channel, waiting for the ACK can therefore take
+    /// longer than the `ack_timeout` passed to [`DW1000::send_with_ack`].
+    ///
+    /// Use `finish_receiving` to return to the `Ready` state afterwards.
+    pub fn wait_ack(&mut self) -> nb::Result<(), Error<SPI>> {
+        if !self.state.transmitted {
+            self.check_transmit()?;
+            self.state.transmitted = true;
+        }
+
+        let mut buffer = [0; 127];
+        let message = match self.wait_receive(&mut buffer) {
+            Ok(message) => message,
+            Err(nb::Error::Other(Error::FrameFilteringRejection)) => {
+                // Some other frame was rejected by the ACK-only frame filter.
+                self.clear_frame_filtering_rejection()
+                    .map_err(nb::Error::Other)?;
+                return self.listen_for_ack();
+            }
+            Err(error) => return Err(error),
+        };
+
+        if message.frame.header.frame_type == mac::FrameType::Acknowledgement
+            && message.frame.header.seq == self.state.seq
+        {
+            return Ok(());
+        }
+
+        // Not the ACK we're waiting for.
+        self.listen_for_ack()
+    }
+
+    /// Turns the receiver on again to keep waiting for the ACK
+    fn listen_for_ack(&mut self) -> nb::Result<(), Error<SPI>> {
+        self.enable_receiver(ReceiveTime::Now)
+            .map_err(nb::Error::Other)?;
+        self.state.finished = false;
+
+        Err(nb::Error::WouldBlock)
+    }
+
+    fn clear_frame_filtering_rejection(&mut self) -> Result<(), Error<SPI>> {
+        self.ll.sys_status().write(|w| w.affrej(0b1))?;
+        self.clear_status()
+    }
+}
@@ -0,0 +1,85 @@
+use embedded_hal::{delay::DelayNs, spi::SpiDevice};
+
+use super::Awake;
+use crate::{Error, Ready, DW1000};
+
+/// A temperature and voltage measurement of the SAR ADC
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureAndVoltage {
+    /// The temperature of the DW1000 in °C
+    pub temperature: f32,
+
+    /// The supply voltage in volts
+    pub voltage: f32,
+}
+
+impl<SPI> DW1000<SPI, Ready>
+where
+    SPI: SpiDevice,
+{
+    /// Measures the temperature of the DW1000 and its supply voltage
+    ///
+    /// The raw readings of the SAR ADC are converted using the factory
+    /// calibration from the OTP memory (see [`DW1000::read_factory_calibration`]).
+    /// If the OTP memory has no calibration, the results are inaccurate.
+    ///
+    /// `delay` is used to wait for the conversion to finish.
+    pub fn read_temperature_and_voltage(
+        &mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<TemperatureAndVoltage, Error<SPI>> {
+        // This is the sequence given in the user manual. Enable the biases of
+        // the sensors and the ADC, then the outputs.
+        self.ll.rf_sensor_bias().write(|w| w.value(0x80))?;
+        self.ll.rf_sensor_ctrl().write(|w| w.value(0x0a))?;
+        self.ll.rf_sensor_ctrl().write(|w| w.value(0x0f))?;
+
+        self.ll.tc_sarc().write(|w| w.sar_ctrl(0))?;
+        self.ll.tc_sarc().write(|w| w.sar_ctrl(1))?;
+
+        delay.delay_ms(1);
+
+        let tc_sarl = self.ll.tc_sarl().read()?;
+        self.ll.tc_sarc().write(|w| w.sar_ctrl(0))?;
+
+        let calibration = self.read_factory_calibration()?;
+
+        Ok(TemperatureAndVoltage {
+            temperature: calibration.temperature_from_raw(tc_sarl.sar_ltemp()),
+            voltage: calibration.voltage_from_raw(tc_sarl.sar_lvbat()),
+        })
+    }
+}
+
+impl<SPI, State> DW1000<SPI, State>
+where
+    SPI: SpiDevice,
+    State: Awake,
+{
+    /// Returns the temperature and voltage measured on the last wake-up
+    ///
+    /// The DW1000 measures the temperature and voltage when waking up from
+    /// sleep, if enabled using [`SleepConfig::measure_on_wakeup`] with
+    /// [`DW1000::enter_sleep_with_config`]. This allows monitoring them
+    /// without the delay of [`DW1000::read_temperature_and_voltage`].
+    ///
+    /// [`SleepConfig::measure_on_wakeup`]: crate::hl::SleepConfig::measure_on_wakeup
+    ///
+    /// Returns `None`, if there was no measurement, for example because the
+    /// DW1000 hasn't been asleep since it was powered up.
+    pub fn read_wakeup_temperature_and_voltage(
+        &mut self,
+    ) -> Result<Option<TemperatureAndVoltage>, Error<SPI>> {
+        let tc_sarw = self.ll.tc_sarw().read()?;
+        if tc_sarw.sar_wtemp() == 0 && tc_sarw.sar_wvbat() == 0 {
+            return Ok(None);
+        }
+
+        let calibration = self.read_factory_calibration()?;
+
+        Ok(Some(TemperatureAndVoltage {
+            temperature: calibration.temperature_from_raw(tc_sarw.sar_wtemp()),
+            voltage: calibration.voltage_from_raw(tc_sarw.sar_wvbat()),
+        }))
+    }
+}
@@ -1,4 +1,5 @@
-use crate::{time::Instant, Error, Ready, Sending, DW1000};
+use super::{sequences, Awake, ReceiveTime, SendTime, SendingWithResponse};
+use crate::{time::Instant, Error, Ready, Sending, SingleBufferReceiving, TxConfig, DW1000};
 use embedded_hal::spi::SpiDevice;
 use nb;
 
@@ -19,61 +20,9 @@ where
     /// DWM1001-Dev board, that the `dwm1001` crate has explicit support for
     /// this.
     pub fn wait_transmit(&mut self) -> nb::Result<Instant, Error<SPI>> {
-        // Check Half Period Warning Counter. If this is a delayed transmission,
-        // this will indicate that the delay was too short, and the frame was
-        // sent too late.
-        let evc_hpw = self
-            .ll
-            .evc_hpw()
-            .read()
-            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?
-            .value();
-        if evc_hpw != 0 {
-            return Err(nb::Error::Other(Error::DelayedSendTooLate));
-        }
-
-        // Check Transmitter Power-Up Warning Counter. If this is a delayed
-        // transmission, this indicates that the transmitter was still powering
-        // up while sending, and the frame preamble might not have transmit
-        // correctly.
-        let evc_tpw = self
-            .ll
-            .evc_tpw()
-            .read()
-            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?
-            .value();
-        if evc_tpw != 0 {
-            return Err(nb::Error::Other(Error::DelayedSendPowerUpWarning));
-        }
-
-        // ATTENTION:
-        // If you're changing anything about which SYS_STATUS flags are being
-        // checked in this method, also make sure to update `enable_interrupts`.
-        let sys_status = self
-            .ll
-            .sys_status()
-            .read()
-            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?;
-
-        // Has the frame been sent?
-        if sys_status.txfrs() == 0b0 {
-            // Frame has not been sent
-            return Err(nb::Error::WouldBlock);
-        }
-
-        // Frame sent
-        self.reset_flags().map_err(nb::Error::Other)?;
+        let tx_timestamp = self.check_transmit()?;
         self.state.finished = true;
 
-        let tx_timestamp = self
-            .ll
-            .tx_time()
-            .read()
-            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?
-            .tx_stamp();
-        // This is safe because the value read from the device will never be higher than the allowed value.
-        let tx_timestamp = unsafe { Instant::new_unchecked(tx_timestamp) };
-
         Ok(tx_timestamp)
     }
 
@@ -90,7 +39,7 @@ where
                 Ok(()) => (),
                 Err(error) => return Err((self, error)),
             }
-            match self.reset_flags() {
+            match self.reset_tx_flags() {
                 Ok(()) => (),
                 Err(error) => return Err((self, error)),
             }
@@ -108,17 +57,132 @@ where
             state: Ready,
         })
     }
+}
+
+impl<SPI> DW1000<SPI, SendingWithResponse>
+where
+    SPI: SpiDevice,
+{
+    /// Wait for the transmission to finish
+    ///
+    /// Works like [`DW1000::wait_transmit`] in the `Sending` state. Once the
+    /// frame has been sent, the receiver is turned on automatically after the
+    /// configured response delay.
+    pub fn wait_transmit(&mut self) -> nb::Result<Instant, Error<SPI>> {
+        let tx_timestamp = self.check_transmit()?;
+        self.state.finished = true;
+
+        Ok(tx_timestamp)
+    }
+
+    /// Finishes sending and moves on to the `SingleBufferReceiving` state
+    ///
+    /// If the send operation has finished, as indicated by `wait_transmit`,
+    /// the receiver has been enabled by the DW1000 itself and the returned
+    /// instance can be used to wait for the response. If the send operation
+    /// is still ongoing, it will be aborted and the receiver is enabled right
+    /// away.
+    #[allow(clippy::type_complexity)]
+    pub fn finish_sending(
+        mut self,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, (Self, Error<SPI>)> {
+        if !self.state.finished {
+            // Can't use `map_err` and `?` here, as the compiler will complain
+            // about `self` moving into the closure.
+            match self.force_idle(false) {
+                Ok(()) => (),
+                Err(error) => return Err((self, error)),
+            }
+            match self.reset_tx_flags() {
+                Ok(()) => (),
+                Err(error) => return Err((self, error)),
+            }
+            match self.enable_receiver(ReceiveTime::Now) {
+                Ok(()) => (),
+                Err(error) => return Err((self, error)),
+            }
+        }
+
+        // A late delayed transmission has already been reported by
+        // `wait_transmit`. Don't let it show up as a late receive.
+        match self.ll.sys_status().write(|w| w.hpdwarn(0b1)) {
+            Ok(_) => {}
+            Err(e) => return Err((self, Error::Spi(e.0))),
+        }
+
+        // Turn off the external transmit synchronization
+        match self.ll.ec_ctrl().modify(|_, w| w.ostsm(0)) {
+            Ok(_) => {}
+            Err(e) => return Err((self, Error::Spi(e.0))),
+        }
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: SingleBufferReceiving {
+                finished: false,
+                config: self.state.config,
+            },
+        })
+    }
+}
+
+impl<SPI, State> DW1000<SPI, State>
+where
+    SPI: SpiDevice,
+    State: Awake,
+{
+    /// Writes the frame and the transmit configuration, without starting the
+    /// transmission
+    pub(super) fn prepare_transmission(
+        &mut self,
+        writer: impl FnOnce(&mut [u8]) -> Result<usize, Error<SPI>>,
+        send_time: &SendTime,
+        config: &TxConfig,
+    ) -> Result<(), Error<SPI>> {
+        sequences::prepare_transmission!(blocking, self, writer, send_time, config)
+    }
+
+    /// Writes a frame of the extended PHR mode into the TX buffer
+    ///
+    /// This is kept out of `prepare_transmission`, so the large buffer is only
+    /// put on the stack when it's actually needed.
+    #[inline(never)]
+    fn write_long_frame(
+        &mut self,
+        writer: impl FnOnce(&mut [u8]) -> Result<usize, Error<SPI>>,
+    ) -> Result<usize, Error<SPI>> {
+        // Leave room for the two-octet CRC
+        let mut buffer = [0; 1023 - 2];
+        let len = writer(&mut buffer)?;
+        if len > buffer.len() {
+            return Err(Error::FrameTooLong { max_len: 1023 });
+        }
+
+        self.ll.write_tx_data(0, &buffer[..len])?;
+
+        Ok(len)
+    }
+
+    /// Starts the prepared transmission
+    ///
+    /// If `wait_for_response` is set, the DW1000 turns on the receiver after
+    /// sending, once the delay configured in ACK_RESP_T has passed.
+    pub(super) fn start_transmission(
+        &mut self,
+        send_time: &SendTime,
+        append_crc: bool,
+        wait_for_response: bool,
+    ) -> Result<(), Error<SPI>> {
+        sequences::start_transmission!(blocking, self, send_time, append_crc, wait_for_response)
+    }
+
+    /// Checks whether the transmission has finished, returning its timestamp
+    pub(super) fn check_transmit(&mut self) -> nb::Result<Instant, Error<SPI>> {
+        sequences::check_transmit!(blocking, self)
+    }
 
-    fn reset_flags(&mut self) -> Result<(), Error<SPI>> {
-        self.ll.sys_status().write(
-            |w| {
-                w.txfrb(0b1) // Transmit Frame Begins
-                    .txprs(0b1) // Transmit Preamble Sent
-                    .txphs(0b1) // Transmit PHY Header Sent
-                    .txfrs(0b1)
-            }, // Transmit Frame Sent
-        )?;
-
-        Ok(())
+    pub(super) fn reset_tx_flags(&mut self) -> Result<(), Error<SPI>> {
+        sequences::reset_tx_flags!(blocking, self)
     }
 }
@@ -0,0 +1,817 @@
+//! Register sequences shared between the blocking and the async driver
+//!
+//! The sequences are written once, as macros, and expanded into the methods
+//! of both drivers. The first argument of every macro selects the SPI
+//! interface: `blocking` uses the blocking register accessors, `async` uses
+//! the `*_async` accessors and awaits them.
+//!
+//! The sequences return early using `?`, and evaluate to the result of the
+//! method they implement.
+
+/// Reads, writes or modifies a register
+macro_rules! reg {
+    (blocking, $ll:expr, $reg:ident.read()) => {
+        $ll.$reg().read()
+    };
+    (blocking, $ll:expr, $reg:ident.write($f:expr $(,)?)) => {
+        $ll.$reg().write($f)
+    };
+    (blocking, $ll:expr, $reg:ident.modify($f:expr $(,)?)) => {
+        $ll.$reg().modify($f)
+    };
+    (async, $ll:expr, $reg:ident.read()) => {
+        $ll.$reg().read_async().await
+    };
+    (async, $ll:expr, $reg:ident.write($f:expr $(,)?)) => {
+        $ll.$reg().write_async($f).await
+    };
+    (async, $ll:expr, $reg:ident.modify($f:expr $(,)?)) => {
+        $ll.$reg().modify_async($f).await
+    };
+}
+
+/// Calls a method that exists in both drivers, awaiting it in the async one
+macro_rules! call {
+    (blocking, $call:expr) => {
+        $call
+    };
+    (async, $call:expr) => {
+        $call.await
+    };
+}
+
+/// Initializes the DW1000, see `DW1000::init`
+///
+/// Unlike the other sequences, this one is a statement, as `init` returns the
+/// DW1000 in its new state.
+macro_rules! init {
+    ($mode:tt, $self:ident, $delay:ident) => {{
+        use $crate::hl::sequences::{call, reg};
+
+        // Set AGC_TUNE1. See user manual, section 2.5.5.1.
+        reg!($mode, $self.ll, agc_tune1.write(|w| w.value(0x8870)))?;
+
+        // Set AGC_TUNE2. See user manual, section 2.5.5.2.
+        reg!($mode, $self.ll, agc_tune2.write(|w| w.value(0x2502A907)))?;
+
+        // Set DRX_TUNE2. See user manual, section 2.5.5.3.
+        reg!($mode, $self.ll, drx_tune2.write(|w| w.value(0x311A002D)))?;
+
+        // Set NTM. See user manual, section 2.5.5.4. This improves performance
+        // in line-of-sight conditions, but might not be the best choice if non-
+        // line-of-sight performance is important.
+        reg!($mode, $self.ll, lde_cfg1.modify(|_, w| w.ntm(0xD)))?;
+
+        // Set LDE_CFG2. See user manual, section 2.5.5.5.
+        reg!($mode, $self.ll, lde_cfg2.write(|w| w.value(0x1607)))?;
+
+        // Set TX_POWER. See user manual, section 2.5.5.6.
+        reg!($mode, $self.ll, tx_power.write(|w| w.value(0x0E082848)))?;
+
+        // Set RF_TXCTRL. See user manual, section 2.5.5.7.
+        reg!(
+            $mode,
+            $self.ll,
+            rf_txctrl.modify(|_, w| w.txmtune(0b1111).txmq(0b111))
+        )?;
+
+        // Set TC_PGDELAY. See user manual, section 2.5.5.8.
+        reg!($mode, $self.ll, tc_pgdelay.write(|w| w.value(0xC0)))?;
+
+        // Set FS_PLLTUNE. See user manual, section 2.5.5.9.
+        reg!($mode, $self.ll, fs_plltune.write(|w| w.value(0xBE)))?;
+
+        // Set LDOTUNE. See user manual, section 2.5.5.11.
+        let ldotune_low = call!($mode, $self.read_otp($crate::hl::otp::LDOTUNE))?;
+        if ldotune_low != 0 {
+            let ldotune_high = call!($mode, $self.read_otp($crate::hl::otp::LDOTUNE + 1))?;
+            let ldotune = ldotune_low as u64 | (ldotune_high as u64) << 32;
+            reg!($mode, $self.ll, ldotune.write(|w| w.value(ldotune)))?;
+        }
+
+        // Set LDELOAD. See user manual, section 2.5.5.10.
+        reg!(
+            $mode,
+            $self.ll,
+            pmsc_ctrl0.modify(|r, w| w.raw_value(r.raw_value() | 0x0301))
+        )?;
+        reg!($mode, $self.ll, otp_ctrl.write(|w| w.ldeload(0b1)))?;
+        call!($mode, $delay.delay_ms(5));
+        reg!(
+            $mode,
+            $self.ll,
+            pmsc_ctrl0.modify(|r, w| w.raw_value(r.raw_value() & !0x0101))
+        )?;
+    }};
+}
+
+/// Reads a word from the OTP memory, see `DW1000::read_otp`
+macro_rules! read_otp {
+    ($mode:tt, $self:ident, $address:expr) => {{
+        use $crate::hl::sequences::reg;
+
+        let address: u16 = $address;
+
+        // Set address
+        reg!($mode, $self.ll, otp_addr.write(|w| w.value(address)))?;
+        // Switch into read mode
+        reg!(
+            $mode,
+            $self.ll,
+            otp_ctrl.write(|w| w.otprden(0b1).otpread(0b1))
+        )?;
+        reg!($mode, $self.ll, otp_ctrl.write(|w| w.otprden(0b1)))?;
+        // Read back value
+        let value = reg!($mode, $self.ll, otp_rdat.read())?.value();
+        // End read mode
+        reg!($mode, $self.ll, otp_ctrl.write(|w| w))?;
+        Ok(value)
+    }};
+}
+
+/// Sets the crystal trim, see `DW1000::set_crystal_trim`
+macro_rules! set_crystal_trim {
+    ($mode:tt, $self:ident, $trim:expr) => {{
+        use $crate::hl::{sequences::reg, CRYSTAL_TRIM_MAX};
+
+        let trim: u8 = $trim;
+        reg!(
+            $mode,
+            $self.ll,
+            fs_xtalt.write(|w| w.xtalt(trim & CRYSTAL_TRIM_MAX).reserved(0b011))
+        )?;
+
+        Ok(())
+    }};
+}
+
+/// Creates a `CrystalTrimmer`, see `DW1000::crystal_trimmer`
+macro_rules! crystal_trimmer {
+    ($mode:tt, $self:ident, $config:expr) => {{
+        use $crate::hl::{otp, sequences::call, CrystalTrimmer, CRYSTAL_TRIM_MAX};
+
+        // An OTP trim of 0 means that no trim has been programmed
+        let trim = call!($mode, $self.read_otp(otp::XTAL_TRIM))? as u8 & CRYSTAL_TRIM_MAX;
+        let trim = if trim != 0 {
+            call!($mode, $self.set_crystal_trim(trim))?;
+            trim
+        } else {
+            call!($mode, $self.get_crystal_trim())?
+        };
+
+        Ok(CrystalTrimmer::new(trim, $config))
+    }};
+}
+
+/// Forces the DW1000 into IDLE mode, see `DW1000::force_idle`
+macro_rules! force_idle {
+    ($mode:tt, $self:ident, $double_buffered:expr) => {{
+        use $crate::hl::sequences::reg;
+
+        let double_buffered: bool = $double_buffered;
+        let mut saved_sys_mask = [0; 5];
+
+        if double_buffered {
+            // Mask the double buffered status bits
+            reg!(
+                $mode,
+                $self.ll,
+                sys_mask.modify(|r, w| {
+                    saved_sys_mask = r.0;
+                    w.mrxfce(0).mrxfcg(0).mrxdfr(0).mldedone(0)
+                })
+            )?;
+        }
+
+        reg!($mode, $self.ll, sys_ctrl.write(|w| w.trxoff(0b1)))?;
+        while reg!($mode, $self.ll, sys_ctrl.read())?.trxoff() == 0b1 {}
+
+        if double_buffered {
+            // Clear the bits
+            reg!(
+                $mode,
+                $self.ll,
+                sys_status.write(|w| {
+                    w.rxprd(0b1) // Receiver Preamble Detected
+                        .rxsfdd(0b1) // Receiver SFD Detected
+                        .ldedone(0b1) // LDE Processing Done
+                        .rxphd(0b1) // Receiver PHY Header Detected
+                        .rxphe(0b1) // Receiver PHY Header Error
+                        .rxdfr(0b1) // Receiver Data Frame Ready
+                        .rxfcg(0b1) // Receiver FCS Good
+                        .rxfce(0b1) // Receiver FCS Error
+                        .rxrfsl(0b1) // Receiver Reed Solomon Frame Sync Loss
+                        .rxrfto(0b1) // Receiver Frame Wait Timeout
+                        .ldeerr(0b1) // Leading Edge Detection Processing Error
+                        .rxovrr(0b1) // Receiver Overrun
+                        .rxpto(0b1) // Preamble Detection Timeout
+                        .rxsfdto(0b1) // Receiver SFD Timeout
+                        .rxrscs(0b1) // Receiver Reed-Solomon Correction Status
+                        .rxprej(0b1) // Receiver Preamble Rejection
+                })
+            )?;
+
+            // Restore the mask
+            reg!(
+                $mode,
+                $self.ll,
+                sys_mask.write(|w| {
+                    w.0.copy_from_slice(&saved_sys_mask);
+                    w
+                })
+            )?;
+        }
+
+        Ok(())
+    }};
+}
+
+/// Writes the channel and SFD settings that are shared between RX and TX
+macro_rules! configure_channel {
+    ($mode:tt, $self:ident, $channel:expr, $prf:expr, $sfd_sequence:expr) => {{
+        use $crate::{configs::SfdSequence, hl::sequences::reg};
+
+        let channel: $crate::configs::UwbChannel = $channel;
+        let prf: $crate::configs::PulseRepetitionFrequency = $prf;
+        let sfd_sequence: SfdSequence = $sfd_sequence;
+
+        reg!(
+            $mode,
+            $self.ll,
+            chan_ctrl.modify(|_, w| {
+                w.tx_chan(channel as u8)
+                    .rx_chan(channel as u8)
+                    .dwsfd(
+                        (sfd_sequence == SfdSequence::Decawave
+                            || sfd_sequence == SfdSequence::DecawaveAlt)
+                            as u8,
+                    )
+                    .rxprf(prf as u8)
+                    .tnssfd(
+                        (sfd_sequence == SfdSequence::User
+                            || sfd_sequence == SfdSequence::DecawaveAlt)
+                            as u8,
+                    )
+                    .rnssfd(
+                        (sfd_sequence == SfdSequence::User
+                            || sfd_sequence == SfdSequence::DecawaveAlt)
+                            as u8,
+                    )
+                    .tx_pcode(channel.get_recommended_preamble_code(prf))
+                    .rx_pcode(channel.get_recommended_preamble_code(prf))
+            })
+        )?;
+
+        match sfd_sequence {
+            SfdSequence::IEEE => {} // IEEE has predefined sfd lengths and the register has no effect.
+            SfdSequence::Decawave => reg!($mode, $self.ll, sfd_length.write(|w| w.value(8)))?, // This isn't entirely necessary as the Decawave8 settings in chan_ctrl already force it to 8
+            SfdSequence::DecawaveAlt => reg!($mode, $self.ll, sfd_length.write(|w| w.value(16)))?, // Set to 16
+            SfdSequence::User => {} // Users are responsible for setting the lengths themselves
+        }
+    }};
+}
+
+/// Applies the receive configuration, see `DW1000::configure_receiver`
+macro_rules! configure_receiver {
+    ($mode:tt, $self:ident, $config:expr, $double_buffered:expr, $auto_rx_reenable:expr) => {{
+        use $crate::{
+            configs::BitRate,
+            hl::sequences::{call, configure_channel, reg},
+        };
+
+        let config: &$crate::RxConfig = $config;
+        let double_buffered: bool = $double_buffered;
+        let auto_rx_reenable: bool = $auto_rx_reenable;
+
+        config.validate(double_buffered)?;
+
+        // For unknown reasons, the DW1000 gets stuck in RX mode without ever
+        // receiving anything, after receiving one good frame. Reset the
+        // receiver to make sure its in a valid state before attempting to
+        // receive anything.
+        reg!(
+            $mode,
+            $self.ll,
+            pmsc_ctrl0.modify(
+                |_, w| w.softreset(0b1110), // reset receiver
+            )
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            pmsc_ctrl0.modify(
+                |_, w| w.softreset(0b1111), // clear reset
+            )
+        )?;
+
+        // We're already resetting the receiver in the previous step, and that's
+        // good enough to make my example program that's both sending and
+        // receiving work very reliably over many hours (that's not to say it
+        // becomes unreliable after those hours, that's just when my test
+        // stopped). However, I've seen problems with an example program that
+        // only received, never sent, data. That got itself into some weird
+        // state where it couldn't receive anymore.
+        // I suspect that's because that example didn't have the following line
+        // of code, while the send/receive example had that line of code, being
+        // called from `send`.
+        // While I haven't, as of this writing, run any hours-long tests to
+        // confirm this does indeed fix the receive-only example, it seems
+        // (based on my eyeball-only measurements) that the RX/TX example is
+        // dropping fewer frames now.
+        call!($mode, $self.force_idle(false))?;
+
+        // The accept bits only have an effect if frame filtering is enabled
+        let filter = config.frame_filtering.unwrap_or_default();
+        reg!(
+            $mode,
+            $self.ll,
+            sys_cfg.modify(|_, w| {
+                w.ffen(config.frame_filtering.is_some() as u8) // enable or disable frame filtering
+                    .ffbc(filter.behave_as_coordinator as u8) // behave as coordinator
+                    .ffab(filter.allow_beacon as u8) // receive beacon frames
+                    .ffad(filter.allow_data as u8) // receive data frames
+                    .ffaa(filter.allow_ack as u8) // receive acknowledgement frames
+                    .ffam(filter.allow_mac_command as u8) // receive MAC command frames
+                    .ffar(filter.allow_reserved as u8) // receive reserved frame types
+                    .ffa4(filter.allow_frame_type_4 as u8) // receive frame type 4
+                    .ffa5(filter.allow_frame_type_5 as u8) // receive frame type 5
+                    // Set the double buffering and auto re-enable
+                    .dis_drxb(!double_buffered as u8)
+                    .rxautr(auto_rx_reenable as u8)
+                    // Set whether the receiver should look for 110kbps or 850/6800kbps messages
+                    .rxm110k((config.bitrate == BitRate::Kbps110) as u8)
+                    // Enable the frame wait timeout, if configured
+                    .rxwtoe(config.frame_wait_timeout.is_some() as u8)
+                    // Enable automatic acknowledgement, if configured
+                    .autoack(config.auto_ack.is_some() as u8)
+                    // Set the PHR mode, which determines the maximum frame length
+                    .phr_mode(config.phr_mode as u8)
+            })
+        )?;
+
+        if let Some(ack_time) = config.auto_ack {
+            reg!($mode, $self.ll, ack_resp_t.modify(|_, w| w.ack_tim(ack_time)))?;
+
+            // The ACK is sent using the transmitter settings, so set those up
+            // to match the receive config.
+            reg!(
+                $mode,
+                $self.ll,
+                tx_fctrl.modify(|_, w| {
+                    w.txbr(config.bitrate as u8)
+                        .txprf(config.pulse_repetition_frequency as u8)
+                        .txpsr(((config.expected_preamble_length as u8) & 0b1100) >> 2)
+                        .pe((config.expected_preamble_length as u8) & 0b0011)
+                })
+            )?;
+            reg!(
+                $mode,
+                $self.ll,
+                rf_txctrl.write(|w| w.value(config.channel.get_recommended_rf_txctrl()))
+            )?;
+            reg!(
+                $mode,
+                $self.ll,
+                tc_pgdelay.write(|w| w.value(config.channel.get_recommended_tc_pgdelay()))
+            )?;
+        }
+
+        // Set the timeouts. A preamble detection timeout of 0 disables it, and
+        // 0x1041 is the default SFD timeout (see the register descriptions of
+        // RX_FWTO, DRX_PRETOC and DRX_SFDTOC in the user manual).
+        if let Some(frame_wait_timeout) = config.frame_wait_timeout {
+            reg!($mode, $self.ll, rx_fwto.write(|w| w.value(frame_wait_timeout)))?;
+        }
+        reg!(
+            $mode,
+            $self.ll,
+            drx_pretoc.write(|w| w.count(config.preamble_detection_timeout.unwrap_or(0)))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            drx_sfdtoc.write(|w| w.count(config.sfd_timeout.unwrap_or(0x1041)))
+        )?;
+
+        // Configure SNIFF mode. An on time of 0 disables it. PLL2 sequencing
+        // is required to switch the receiver off during the off time.
+        let sniff_mode = config.sniff_mode;
+        reg!(
+            $mode,
+            $self.ll,
+            rx_sniff.write(|w| match sniff_mode {
+                Some(sniff_mode) => w
+                    .sniff_ont(sniff_mode.on_time)
+                    .sniff_offt(sniff_mode.off_time_us),
+                None => w,
+            })
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            pmsc_ctrl0.modify(|_, w| w.pll2_seq_en(sniff_mode.is_some() as u8))
+        )?;
+
+        // Timeout flags from a previous receive operation would otherwise
+        // immediately fail this one.
+        reg!(
+            $mode,
+            $self.ll,
+            sys_status.write(|w| w.rxrfto(0b1).rxpto(0b1).rxsfdto(0b1))
+        )?;
+
+        // Set PLLLDT bit in EC_CTRL. According to the documentation of the
+        // CLKPLL_LL bit in SYS_STATUS, this bit needs to be set to ensure the
+        // reliable operation of the CLKPLL_LL bit. Since I've seen that bit
+        // being set, I want to make sure I'm not just seeing crap.
+        reg!($mode, $self.ll, ec_ctrl.modify(|_, w| w.pllldt(0b1)))?;
+
+        // Now that PLLLDT is set, clear all bits in SYS_STATUS that depend on
+        // it for reliable operation. After that is done, these bits should work
+        // reliably.
+        reg!(
+            $mode,
+            $self.ll,
+            sys_status.write(|w| w.cplock(0b1).clkpll_ll(0b1))
+        )?;
+
+        // Apply the config
+        configure_channel!(
+            $mode,
+            $self,
+            config.channel,
+            config.pulse_repetition_frequency,
+            config.sfd_sequence
+        );
+
+        // Set general tuning
+        reg!(
+            $mode,
+            $self.ll,
+            drx_tune0b.write(|w| w.value(
+                config
+                    .bitrate
+                    .get_recommended_drx_tune0b(config.sfd_sequence)
+            ))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            drx_tune1a.write(|w| w.value(
+                config
+                    .pulse_repetition_frequency
+                    .get_recommended_drx_tune1a()
+            ))
+        )?;
+        let drx_tune1b = config
+            .expected_preamble_length
+            .get_recommended_drx_tune1b(config.bitrate)?;
+        reg!($mode, $self.ll, drx_tune1b.write(|w| w.value(drx_tune1b)))?;
+        let drx_tune2 = config
+            .pulse_repetition_frequency
+            .get_recommended_drx_tune2(config.expected_preamble_length.get_recommended_pac_size())?;
+        reg!($mode, $self.ll, drx_tune2.write(|w| w.value(drx_tune2)))?;
+        reg!(
+            $mode,
+            $self.ll,
+            drx_tune4h.write(|w| w.value(config.expected_preamble_length.get_recommended_dxr_tune4h()))
+        )?;
+
+        // Set channel tuning
+        reg!(
+            $mode,
+            $self.ll,
+            rf_rxctrlh.write(|w| w.value(config.channel.get_recommended_rf_rxctrlh()))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            fs_pllcfg.write(|w| w.value(config.channel.get_recommended_fs_pllcfg()))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            fs_plltune.write(|w| w.value(config.channel.get_recommended_fs_plltune()))
+        )?;
+
+        // Set the LDE registers
+        reg!(
+            $mode,
+            $self.ll,
+            lde_cfg2.write(|w| w.value(config.pulse_repetition_frequency.get_recommended_lde_cfg2()))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            lde_repc.write(|w| {
+                w.value(config.channel.get_recommended_lde_repc_value(
+                    config.pulse_repetition_frequency,
+                    config.bitrate,
+                ))
+            })
+        )?;
+
+        // Check if the rx buffer pointer is correct
+        let status = reg!($mode, $self.ll, sys_status.read())?;
+        if status.hsrbp() != status.icrbp() {
+            // The RX Buffer Pointer of the host and the ic side don't point to the same one.
+            // We need to switch over
+            reg!($mode, $self.ll, sys_ctrl.modify(|_, w| w.hrbpt(1)))?;
+        }
+
+        Ok(())
+    }};
+}
+
+/// Enables the receiver, see `DW1000::enable_receiver`
+macro_rules! enable_receiver {
+    ($mode:tt, $self:ident, $receive_time:expr) => {{
+        use $crate::hl::{sequences::reg, ReceiveTime};
+
+        let receive_time: ReceiveTime = $receive_time;
+
+        // Clear the half period delay warning, so `wait_receive` can tell
+        // whether a delayed receive was started too late.
+        reg!($mode, $self.ll, sys_status.write(|w| w.hpdwarn(0b1)))?;
+
+        if let ReceiveTime::Delayed(time) = receive_time {
+            // Put the time into the delay register
+            // By setting this register, the chip knows to delay before receiving
+            reg!($mode, $self.ll, dx_time.write(|w| w.value(time.value())))?;
+        }
+
+        // Start receiving
+        reg!(
+            $mode,
+            $self.ll,
+            sys_ctrl.modify(|_, w| {
+                w.rxdlye(matches!(receive_time, ReceiveTime::Delayed(_)) as u8)
+                    .rxenab(0b1)
+            })
+        )?;
+
+        Ok(())
+    }};
+}
+
+/// Writes the frame and the transmit configuration, see
+/// `DW1000::prepare_transmission`
+macro_rules! prepare_transmission {
+    ($mode:tt, $self:ident, $writer:expr, $send_time:expr, $config:expr) => {{
+        use $crate::{
+            configs::PhrMode,
+            hl::{
+                sequences::{call, configure_channel, reg},
+                SendTime,
+            },
+        };
+
+        let writer = $writer;
+        let send_time: &SendTime = $send_time;
+        let config: &$crate::TxConfig = $config;
+
+        // Clear event counters
+        reg!($mode, $self.ll, evc_ctrl.write(|w| w.evc_clr(0b1)))?;
+        while reg!($mode, $self.ll, evc_ctrl.read())?.evc_clr() == 0b1 {}
+
+        // (Re-)Enable event counters
+        reg!($mode, $self.ll, evc_ctrl.write(|w| w.evc_en(0b1)))?;
+        while reg!($mode, $self.ll, evc_ctrl.read())?.evc_en() == 0b1 {}
+
+        // Sometimes, for unknown reasons, the DW1000 gets stuck in RX mode.
+        // Starting the transmitter won't get it to enter TX mode, which means
+        // all subsequent send operations will fail. Let's disable the
+        // transceiver and force the chip into IDLE mode to make sure that
+        // doesn't happen.
+        call!($mode, $self.force_idle(false))?;
+
+        match *send_time {
+            SendTime::Delayed(time) => {
+                // Put the time into the delay register
+                // By setting this register, the chip knows to delay before transmitting
+                reg!($mode, $self.ll, dx_time.write(|w| w.value(time.value())))?;
+            }
+            SendTime::OnSync => {
+                reg!($mode, $self.ll, ec_ctrl.modify(|_, w| w.wait(33).ostsm(1)))?;
+            }
+            _ => {}
+        }
+
+        // Prepare transmitter
+        let max_len = config.phr_mode.max_frame_len();
+        let len = match config.phr_mode {
+            PhrMode::Standard => {
+                let mut len = Ok(0);
+                reg!(
+                    $mode,
+                    $self.ll,
+                    tx_buffer.write(|w| {
+                        // Leave room for the two-octet CRC
+                        len = writer(&mut w.data()[..max_len - 2]);
+                        w
+                    })
+                )?;
+                len?
+            }
+            PhrMode::Extended => call!($mode, $self.write_long_frame(writer))?,
+        };
+        if len > max_len - 2 {
+            return Err($crate::Error::FrameTooLong { max_len });
+        }
+
+        reg!(
+            $mode,
+            $self.ll,
+            sys_cfg.modify(|_, w| w.phr_mode(config.phr_mode as u8))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            tx_fctrl.modify(|_, w| {
+                // data length + two-octet CRC, split over the standard length
+                // field and the non-standard length extension
+                let frame_len = len as u16 + 2;
+                w.tflen((frame_len & 0x7f) as u8)
+                    .tfle((frame_len >> 7) as u8)
+                    .txboffs(0) // no offset in TX_BUFFER
+                    .txbr(config.bitrate as u8) // configured bitrate
+                    .tr(config.ranging_enable as u8) // configured ranging bit
+                    .txprf(config.pulse_repetition_frequency as u8) // configured PRF
+                    .txpsr(((config.preamble_length as u8) & 0b1100) >> 2) // first two bits of configured preamble length
+                    .pe((config.preamble_length as u8) & 0b0011) // last two bits of configured preamble length
+            })
+        )?;
+
+        // Set the channel and sfd settings
+        configure_channel!(
+            $mode,
+            $self,
+            config.channel,
+            config.pulse_repetition_frequency,
+            config.sfd_sequence
+        );
+
+        // Tune for the correct channel
+        reg!(
+            $mode,
+            $self.ll,
+            rf_txctrl.write(|w| w.value(config.channel.get_recommended_rf_txctrl()))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            tc_pgdelay.write(|w| w.value(config.channel.get_recommended_tc_pgdelay()))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            fs_pllcfg.write(|w| w.value(config.channel.get_recommended_fs_pllcfg()))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            fs_plltune.write(|w| w.value(config.channel.get_recommended_fs_plltune()))
+        )?;
+
+        // Set the LDE registers
+        reg!(
+            $mode,
+            $self.ll,
+            lde_cfg2.modify(|_, w| w.value(config.pulse_repetition_frequency.get_recommended_lde_cfg2()))
+        )?;
+        reg!(
+            $mode,
+            $self.ll,
+            lde_repc.write(|w| {
+                w.value(config.channel.get_recommended_lde_repc_value(
+                    config.pulse_repetition_frequency,
+                    config.bitrate,
+                ))
+            })
+        )?;
+
+        // The TX power is not part of the config, as it depends on the
+        // calibration of the device. See `DW1000::set_tx_power`.
+
+        Ok(())
+    }};
+}
+
+/// Starts the prepared transmission, see `DW1000::start_transmission`
+macro_rules! start_transmission {
+    ($mode:tt, $self:ident, $send_time:expr, $append_crc:expr, $wait_for_response:expr) => {{
+        use $crate::hl::{sequences::reg, SendTime};
+
+        let send_time: &SendTime = $send_time;
+        let append_crc: bool = $append_crc;
+        let wait_for_response: bool = $wait_for_response;
+
+        reg!(
+            $mode,
+            $self.ll,
+            sys_ctrl.modify(|_, w| {
+                // Do we want to suppress crc generation?
+                let w = w.sfcst(!append_crc as u8);
+                // Should the receiver be turned on after sending?
+                let w = w.wait4resp(wait_for_response as u8);
+
+                if !matches!(send_time, SendTime::OnSync) {
+                    // Start transmission
+                    if matches!(send_time, SendTime::Delayed(_)) {
+                        w.txdlys(0b1)
+                    } else {
+                        w
+                    }
+                    .txstrt(0b1)
+                } else {
+                    w
+                }
+            })
+        )?;
+
+        Ok(())
+    }};
+}
+
+/// Checks whether the transmission has finished, see
+/// `DW1000::check_transmit`
+macro_rules! check_transmit {
+    ($mode:tt, $self:ident) => {{
+        use $crate::{
+            hl::sequences::{call, reg},
+            time::Instant,
+            Error,
+        };
+
+        // Check Half Period Warning Counter. If this is a delayed transmission,
+        // this will indicate that the delay was too short, and the frame was
+        // sent too late.
+        let evc_hpw = reg!($mode, $self.ll, evc_hpw.read())
+            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?
+            .value();
+        if evc_hpw != 0 {
+            return Err(nb::Error::Other(Error::DelayedSendTooLate));
+        }
+
+        // Check Transmitter Power-Up Warning Counter. If this is a delayed
+        // transmission, this indicates that the transmitter was still powering
+        // up while sending, and the frame preamble might not have transmit
+        // correctly.
+        let evc_tpw = reg!($mode, $self.ll, evc_tpw.read())
+            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?
+            .value();
+        if evc_tpw != 0 {
+            return Err(nb::Error::Other(Error::DelayedSendPowerUpWarning));
+        }
+
+        // ATTENTION:
+        // If you're changing anything about which SYS_STATUS flags are being
+        // checked in this method, also make sure to update `enable_interrupts`
+        // and the mask in the async `send_with_writer`.
+        let sys_status = reg!($mode, $self.ll, sys_status.read())
+            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?;
+
+        // Has the frame been sent?
+        if sys_status.txfrs() == 0b0 {
+            // Frame has not been sent
+            return Err(nb::Error::WouldBlock);
+        }
+
+        // Frame sent
+        call!($mode, $self.reset_tx_flags()).map_err(nb::Error::Other)?;
+
+        let tx_timestamp = reg!($mode, $self.ll, tx_time.read())
+            .map_err(|error| nb::Error::Other(Error::Spi(error.0)))?
+            .tx_stamp();
+        // This is safe because the value read from the device will never be higher than the allowed value.
+        let tx_timestamp = unsafe { Instant::new_unchecked(tx_timestamp) };
+
+        Ok(tx_timestamp)
+    }};
+}
+
+/// Clears the transmit status flags
+macro_rules! reset_tx_flags {
+    ($mode:tt, $self:ident) => {{
+        use $crate::hl::sequences::reg;
+
+        reg!(
+            $mode,
+            $self.ll,
+            sys_status.write(|w| {
+                w.txfrb(0b1) // Transmit Frame Begins
+                    .txprs(0b1) // Transmit Preamble Sent
+                    .txphs(0b1) // Transmit PHY Header Sent
+                    .txfrs(0b1) // Transmit Frame Sent
+            })
+        )?;
+
+        Ok(())
+    }};
+}
+
+pub(super) use {
+    call, check_transmit, configure_channel, configure_receiver, crystal_trimmer, enable_receiver,
+    force_idle, init, prepare_transmission, read_otp, reg, reset_tx_flags, set_crystal_trim,
+    start_transmission,
+};
@@ -1,13 +1,420 @@
-use crate::{Error, Ready, Sleeping, DW1000};
+use super::{otp, Asleep};
+use crate::{
+    time::Duration, Error, Ready, RxConfig, SingleBufferReceiving, Sleeping, SleepingReceiveOnWake,
+    DW1000,
+};
 use embedded_hal::delay::DelayNs;
 use embedded_hal::spi::SpiDevice;
 
+/// The AON memory address of the high byte of the LP oscillator calibration
+const AON_LPOSC_CAL_HIGH: u8 = 118;
+
+/// The AON memory address of the low byte of the LP oscillator calibration
+const AON_LPOSC_CAL_LOW: u8 = 117;
+
+/// Configuration of the sleep mode
+///
+/// Determines how the DW1000 wakes up and what it does afterwards. Use
+/// [`DW1000::enter_sleep_with_config`] or
+/// [`DW1000::enter_sleep_and_receive_on_wake`] to enter sleep mode.
+///
+/// The default configuration wakes up on SPI access only.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SleepConfig {
+    wake_after: Option<u16>,
+    wake_on_spi: bool,
+    wake_on_pin: bool,
+    irq_on_wakeup: bool,
+    preserve_sleep: bool,
+    sleep_after_rx: bool,
+    sleep_after_tx: bool,
+    measure_on_wakeup: bool,
+}
+
+impl SleepConfig {
+    /// Creates the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wakes up after the given number of sleep counter ticks
+    ///
+    /// Every tick is ~431 ms, but there may be a significant deviation from
+    /// this due to the chip's manufacturing process. Use
+    /// [`DW1000::calibrate_sleep_counter`] to measure it, and
+    /// [`SleepConfig::wake_after_ms`] to convert a time into ticks.
+    pub fn wake_after(mut self, ticks: u16) -> Self {
+        self.wake_after = Some(ticks);
+        self
+    }
+
+    /// Wakes up after the given time
+    ///
+    /// `tick_us` is the length of a sleep counter tick, as returned by
+    /// [`DW1000::calibrate_sleep_counter`]. The time is rounded to the nearest
+    /// tick, but at least one tick is used.
+    pub fn wake_after_ms(self, ms: u32, tick_us: u32) -> Self {
+        let tick_us = (tick_us as u64).max(1);
+        let ticks = (ms as u64 * 1000 + tick_us / 2) / tick_us;
+
+        self.wake_after(ticks.clamp(1, u16::MAX as u64) as u16)
+    }
+
+    /// Wakes up when the chip select line is held low
+    ///
+    /// This is required by the `wake_up` methods of the sleeping states.
+    /// Enabled by default.
+    pub fn wake_on_spi(mut self, enable: bool) -> Self {
+        self.wake_on_spi = enable;
+        self
+    }
+
+    /// Wakes up when the WAKEUP pin is asserted
+    pub fn wake_on_pin(mut self, enable: bool) -> Self {
+        self.wake_on_pin = enable;
+        self
+    }
+
+    /// Asserts the IRQ pin when the radio wakes up
+    pub fn irq_on_wakeup(mut self, enable: bool) -> Self {
+        self.irq_on_wakeup = enable;
+        self
+    }
+
+    /// Preserves the automatic sleep settings over the wake-up
+    ///
+    /// If set, the settings of [`SleepConfig::sleep_after_rx`] and
+    /// [`SleepConfig::sleep_after_tx`] (the ARXSLP and ATXSLP bits in
+    /// PMSC_CTRL1) are kept after waking up. Otherwise, they are cleared on
+    /// wake-up.
+    pub fn preserve_sleep(mut self, enable: bool) -> Self {
+        self.preserve_sleep = enable;
+        self
+    }
+
+    /// Goes back to sleep automatically after receiving a frame
+    ///
+    /// This only has an effect together with
+    /// [`SleepConfig::preserve_sleep`], as the setting is cleared on wake-up
+    /// otherwise. Combined with [`DW1000::enter_sleep_and_receive_on_wake`]
+    /// and [`SleepConfig::wake_after`], the DW1000 periodically wakes up,
+    /// listens, and goes back to sleep by itself.
+    pub fn sleep_after_rx(mut self, enable: bool) -> Self {
+        self.sleep_after_rx = enable;
+        self
+    }
+
+    /// Goes back to sleep automatically after transmitting a frame
+    ///
+    /// This only has an effect together with
+    /// [`SleepConfig::preserve_sleep`], as the setting is cleared on wake-up
+    /// otherwise.
+    pub fn sleep_after_tx(mut self, enable: bool) -> Self {
+        self.sleep_after_tx = enable;
+        self
+    }
+
+    /// Measures the temperature and voltage on wake-up
+    ///
+    /// See [`DW1000::read_wakeup_temperature_and_voltage`].
+    pub fn measure_on_wakeup(mut self, enable: bool) -> Self {
+        self.measure_on_wakeup = enable;
+        self
+    }
+
+    /// Returns true if at least one wake-up source is enabled
+    fn can_wake_up(&self) -> bool {
+        self.wake_after.is_some() || self.wake_on_spi || self.wake_on_pin
+    }
+}
+
+impl Default for SleepConfig {
+    fn default() -> Self {
+        Self {
+            wake_after: None,
+            wake_on_spi: true,
+            wake_on_pin: false,
+            irq_on_wakeup: false,
+            preserve_sleep: false,
+            sleep_after_rx: false,
+            sleep_after_tx: false,
+            measure_on_wakeup: false,
+        }
+    }
+}
+
+/// Returns the length of a sleep counter tick in µs
+///
+/// `high` and `low` are the bytes of the LP oscillator calibration, which is
+/// the number of XTAL/2 (19.2 MHz) cycles per LP oscillator cycle. The sleep
+/// counter is incremented every 4096 LP oscillator cycles.
+fn sleep_tick_us(high: u8, low: u8) -> u32 {
+    let cycles = u16::from_be_bytes([high, low]) as u64;
+    (cycles * 4096 * 10 / 192) as u32
+}
+
+impl<SPI> DW1000<SPI, Ready>
+where
+    SPI: SpiDevice,
+{
+    /// Puts the dw1000 into sleep mode.
+    ///
+    /// - `irq_on_wakeup`: When set to true, the IRQ pin will be asserted when the radio wakes up
+    /// - `sleep_duration`: When `None`, the radio will not wake up by itself and go into the deep sleep mode.
+    ///   When `Some`, then the radio will wake itself up after the given time. Every tick is ~431ms, but there may
+    ///   be a significant deviation from this due to the chip's manufacturing process.
+    ///
+    /// See [`DW1000::enter_sleep_with_config`] for more options, like
+    /// measuring the temperature and voltage on wake-up.
+    ///
+    /// *Note: The SPI speed may be at most 3 Mhz when calling this function.*
+    pub fn enter_sleep(
+        self,
+        irq_on_wakeup: bool,
+        sleep_duration: Option<u16>,
+    ) -> Result<DW1000<SPI, Sleeping>, Error<SPI>> {
+        let mut config = SleepConfig::new().irq_on_wakeup(irq_on_wakeup);
+        if let Some(ticks) = sleep_duration {
+            config = config.wake_after(ticks);
+        }
+
+        self.enter_sleep_with_config(&config)
+    }
+
+    /// Puts the dw1000 into sleep mode, using the given configuration
+    ///
+    /// Returns [`Error::InvalidConfiguration`], if no wake-up source is
+    /// enabled.
+    ///
+    /// *Note: The SPI speed may be at most 3 Mhz when calling this function.*
+    pub fn enter_sleep_with_config(
+        mut self,
+        config: &SleepConfig,
+    ) -> Result<DW1000<SPI, Sleeping>, Error<SPI>> {
+        let tx_antenna_delay = self.prepare_sleep(config, false)?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: Sleeping { tx_antenna_delay },
+        })
+    }
+
+    /// Puts the dw1000 into sleep mode, and starts receiving on wake-up
+    ///
+    /// The receiver is configured using `rx_config` before going to sleep.
+    /// This configuration is restored from the AON memory on wake-up, and the
+    /// receiver is enabled without any involvement of the host. Together with
+    /// [`SleepConfig::wake_after`], this allows low duty cycle listening:
+    /// The DW1000 wakes up periodically, and the host is only involved once
+    /// the IRQ pin signals a received frame or a timeout (for example, the
+    /// preamble detection timeout of `rx_config`).
+    ///
+    /// Returns [`Error::InvalidConfiguration`], if no wake-up source is
+    /// enabled.
+    ///
+    /// *Note: The SPI speed may be at most 3 Mhz when calling this function.*
+    pub fn enter_sleep_and_receive_on_wake(
+        mut self,
+        config: &SleepConfig,
+        rx_config: RxConfig,
+    ) -> Result<DW1000<SPI, SleepingReceiveOnWake>, Error<SPI>> {
+        self.configure_receiver(&rx_config, false, false)?;
+        let tx_antenna_delay = self.prepare_sleep(config, true)?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: SleepingReceiveOnWake {
+                tx_antenna_delay,
+                config: rx_config,
+            },
+        })
+    }
+
+    /// Measures the length of a sleep counter tick
+    ///
+    /// The sleep counter is driven by the internal low power oscillator, whose
+    /// frequency varies significantly between chips. This measures the period
+    /// of the oscillator against the crystal, and returns the length of a
+    /// sleep counter tick in µs. Pass it to [`SleepConfig::wake_after_ms`].
+    pub fn calibrate_sleep_counter(&mut self, delay: &mut impl DelayNs) -> Result<u32, Error<SPI>> {
+        // Run the calibration of the low power oscillator
+        self.ll.aon_cfg1().write(|w| w.lposc_cal(1))?;
+        self.upload_aon_config()?;
+        self.ll.aon_cfg1().write(|w| w)?;
+        self.upload_aon_config()?;
+
+        // Run the system clock from the crystal while reading the result
+        let sysclks = self.ll.pmsc_ctrl0().read()?.sysclks();
+        self.ll.pmsc_ctrl0().modify(|_, w| w.sysclks(0b01))?;
+        delay.delay_ms(1);
+
+        let high = self.read_aon(AON_LPOSC_CAL_HIGH)?;
+        let low = self.read_aon(AON_LPOSC_CAL_LOW)?;
+
+        self.ll.pmsc_ctrl0().modify(|_, w| w.sysclks(sysclks))?;
+
+        Ok(sleep_tick_us(high, low))
+    }
+
+    /// Reads a byte from the AON memory
+    fn read_aon(&mut self, address: u8) -> Result<u8, Error<SPI>> {
+        self.ll.aon_addr().write(|w| w.value(address))?;
+        self.ll.aon_ctrl().write(|w| w.dca_enab(1))?;
+        self.ll.aon_ctrl().write(|w| w.dca_enab(1).dca_read(1))?;
+        self.ll.aon_ctrl().write(|w| w)?;
+
+        Ok(self.ll.aon_rdat().read()?.value())
+    }
+
+    /// Sets up the AON configuration and enters sleep mode
+    ///
+    /// Returns the TX antenna delay, which needs to be restored on wake-up.
+    fn prepare_sleep(
+        &mut self,
+        config: &SleepConfig,
+        receive_on_wake: bool,
+    ) -> Result<Duration, Error<SPI>> {
+        if !config.can_wake_up() {
+            return Err(Error::InvalidConfiguration);
+        }
+
+        // Set the sleep timer
+        if let Some(ticks) = config.wake_after {
+            self.ll.pmsc_ctrl0().modify(|_, w| {
+                w
+                    // Force the 19.2Mhz clock
+                    .sysclks(0b01)
+            })?;
+
+            // Disable the sleep counter
+            self.ll
+                .aon_cfg1()
+                .write(|w| w.sleep_cen(0).smxx(0).lposc_cal(0))?;
+            // Set the counter
+            self.ll.aon_cfg0().write(|w| w.sleep_tim(ticks))?;
+            // Enable the sleep counter
+            self.ll.aon_cfg1().write(|w| w.sleep_cen(1).lposc_cal(1))?;
+            self.upload_aon_config()?;
+
+            self.ll.pmsc_ctrl0().modify(|_, w| {
+                w
+                    // Auto clock
+                    .sysclks(0b00)
+            })?;
+        }
+
+        // Save the settings that the AON memory doesn't store
+        let tx_antenna_delay = self.get_tx_antenna_delay()?;
+
+        // Setup the interrupt.
+        if config.irq_on_wakeup {
+            self.ll
+                .sys_mask()
+                .modify(|_, w| w.mslp2init(1).mcplock(1))?;
+        }
+
+        // Does the chip have the ldo tune calibrated?
+        let lldo = self.read_otp(otp::LDOTUNE)? != 0;
+
+        // Setup everything that needs to be stored in AON
+        self.ll.aon_wcfg().modify(|_, w| {
+            w.onw_radc(config.measure_on_wakeup as u8)
+                .onw_rx(receive_on_wake as u8)
+                .onw_ldc(1)
+                .onw_llde(1)
+                .onw_lldo(lldo as u8)
+                .onw_l64p(1)
+                .pres_sleep(config.preserve_sleep as u8)
+        })?;
+
+        // Setup automatic sleep after RX/TX, which the AON memory restores
+        self.ll.pmsc_ctrl1().modify(|_, w| {
+            w.arxslp(config.sleep_after_rx as u8)
+                .atxslp(config.sleep_after_tx as u8)
+        })?;
+
+        // Setup the wakeup sources.
+        self.ll.aon_cfg0().modify(|_, w| {
+            w.wake_spi(config.wake_on_spi as u8)
+                .wake_pin(config.wake_on_pin as u8)
+                .wake_cnt(config.wake_after.is_some() as u8)
+                .sleep_en(1)
+        })?;
+
+        // Upload always on array configuration and enter sleep
+        self.ll.aon_ctrl().write(|w| w)?;
+        self.ll.aon_ctrl().write(|w| w.save(1))?;
+
+        Ok(tx_antenna_delay)
+    }
+
+    /// Uploads the AON block configuration
+    fn upload_aon_config(&mut self) -> Result<(), Error<SPI>> {
+        self.ll.aon_ctrl().write(|w| w.upl_cfg(1))?;
+        self.ll.aon_ctrl().write(|w| w.upl_cfg(0))?;
+
+        Ok(())
+    }
+}
+
 impl<SPI> DW1000<SPI, Sleeping>
 where
     SPI: SpiDevice,
 {
     /// Wakes the radio up.
     pub fn wake_up(mut self, delay: &mut impl DelayNs) -> Result<DW1000<SPI, Ready>, Error<SPI>> {
+        let tx_antenna_delay = self.state.tx_antenna_delay;
+        self.wake_up_radio(delay, tx_antenna_delay)?;
+
+        // All other values should be restored, so return the ready radio.
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: Ready,
+        })
+    }
+}
+
+impl<SPI> DW1000<SPI, SleepingReceiveOnWake>
+where
+    SPI: SpiDevice,
+{
+    /// Wakes the radio up, if it isn't awake already
+    ///
+    /// The receiver is enabled by the DW1000 itself on wake-up, so the
+    /// returned instance can be used to wait for a frame right away. If the
+    /// DW1000 has woken up by itself before, the frame might already have
+    /// been received.
+    pub fn wake_up(
+        mut self,
+        delay: &mut impl DelayNs,
+    ) -> Result<DW1000<SPI, SingleBufferReceiving>, Error<SPI>> {
+        let tx_antenna_delay = self.state.tx_antenna_delay;
+        self.wake_up_radio(delay, tx_antenna_delay)?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: SingleBufferReceiving {
+                finished: false,
+                config: self.state.config,
+            },
+        })
+    }
+}
+
+impl<SPI, State> DW1000<SPI, State>
+where
+    SPI: SpiDevice,
+    State: Asleep,
+{
+    fn wake_up_radio(
+        &mut self,
+        delay: &mut impl DelayNs,
+        tx_antenna_delay: Duration,
+    ) -> Result<(), Error<SPI>> {
         // Wake up using the spi
         self.ll.wake_up(850 * 2)?;
 
@@ -24,14 +431,21 @@ where
         self.ll.sys_status().write(|w| w.slp2init(1).cplock(1))?;
 
         // Restore the tx antenna delay
-        let delay = self.state.tx_antenna_delay;
-        self.ll.tx_antd().write(|w| w.value(delay.value() as u16))?;
+        self.ll
+            .tx_antd()
+            .write(|w| w.value(tx_antenna_delay.value() as u16))?;
 
-        // All other values should be restored, so return the ready radio.
-        Ok(DW1000 {
-            ll: self.ll,
-            seq: self.seq,
-            state: Ready,
-        })
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_tick_uses_high_byte_first() {
+        // 0x07E4 = 2020 cycles, which is the nominal tick of ~431 ms
+        assert_eq!(sleep_tick_us(0x07, 0xE4), 430_933);
     }
 }
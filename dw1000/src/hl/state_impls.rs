@@ -14,6 +14,14 @@ pub struct Sending {
     pub(super) finished: bool,
 }
 
+/// Indicates that the `DW1000` instance is currently sending, and will start
+/// receiving the response afterwards
+#[derive(Debug)]
+pub struct SendingWithResponse {
+    pub(super) finished: bool,
+    pub(super) config: RxConfig,
+}
+
 /// Indicates that the `DW1000` instance is currently receiving in single buffer mode (default)
 #[derive(Debug)]
 pub struct SingleBufferReceiving {
@@ -28,6 +36,16 @@ pub struct AutoDoubleBufferReceiving {
     pub(super) config: RxConfig,
 }
 
+/// Indicates that the `DW1000` instance has sent a frame and is waiting for its
+/// acknowledgement
+#[derive(Debug)]
+pub struct AwaitingAck {
+    pub(super) finished: bool,
+    pub(super) transmitted: bool,
+    pub(super) seq: u8,
+    pub(super) config: RxConfig,
+}
+
 /// Indicates that the `DW1000` instance is currently sleeping
 #[derive(Debug)]
 pub struct Sleeping {
@@ -35,16 +53,47 @@ pub struct Sleeping {
     pub(super) tx_antenna_delay: Duration,
 }
 
+/// Indicates that the `DW1000` instance is sleeping, and will start receiving
+/// when it wakes up
+///
+/// See [`DW1000::enter_sleep_and_receive_on_wake`].
+///
+/// [`DW1000::enter_sleep_and_receive_on_wake`]: crate::DW1000::enter_sleep_and_receive_on_wake
+#[derive(Debug)]
+pub struct SleepingReceiveOnWake {
+    pub(super) tx_antenna_delay: Duration,
+    pub(super) config: RxConfig,
+}
+
+/// Indicates that the `DW1000` instance is in a test mode
+///
+/// See [`DW1000::start_continuous_wave`] and
+/// [`DW1000::start_continuous_frame`].
+///
+/// [`DW1000::start_continuous_wave`]: crate::DW1000::start_continuous_wave
+/// [`DW1000::start_continuous_frame`]: crate::DW1000::start_continuous_frame
+#[derive(Debug)]
+pub struct TestMode {
+    /// The registers changed by the test modes, to be restored afterwards
+    pub(super) pmsc_ctrl0: u32,
+    pub(super) pmsc_ctrl1: u32,
+    pub(super) pmsc_txfseq: u16,
+}
+
 /// Any state struct that implements this trait signals that the radio is **not** sleeping.
 pub trait Awake {}
 impl Awake for Uninitialized {}
 impl Awake for Ready {}
 impl Awake for Sending {}
+impl Awake for SendingWithResponse {}
 impl Awake for SingleBufferReceiving {}
 impl Awake for AutoDoubleBufferReceiving {}
+impl Awake for AwaitingAck {}
+impl Awake for TestMode {}
 /// Any state struct that implements this trait signals that the radio is sleeping.
 pub trait Asleep {}
 impl Asleep for Sleeping {}
+impl Asleep for SleepingReceiveOnWake {}
 
 /// Any state struct that implements this trait shares a number of rx operations
 pub trait Receiving: Awake {
@@ -93,3 +142,19 @@ impl Receiving for AutoDoubleBufferReceiving {
         &self.config
     }
 }
+impl Receiving for AwaitingAck {
+    const AUTO_RX_REENABLE: bool = false;
+    const DOUBLE_BUFFERED: bool = false;
+
+    fn mark_finished(&mut self) {
+        self.finished = true;
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn get_rx_config(&self) -> &RxConfig {
+        &self.config
+    }
+}
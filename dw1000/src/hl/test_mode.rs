@@ -0,0 +1,179 @@
+use embedded_hal::spi::SpiDevice;
+
+use super::{SendTime, TestMode};
+use crate::{configs::UwbChannel, time::Duration, Error, Ready, TxConfig, DW1000};
+
+/// The minimum frame period of the continuous frame mode, in DW1000 time units
+const MIN_FRAME_PERIOD: u64 = 4 * 512;
+
+impl<SPI> DW1000<SPI, Ready>
+where
+    SPI: SpiDevice,
+{
+    /// Starts transmitting a continuous wave on the given channel
+    ///
+    /// This is intended for RF tests, like regulatory pre-compliance or
+    /// production tests. The carrier is transmitted with the TX power that is
+    /// currently configured, until [`DW1000::stop`] is called.
+    pub fn start_continuous_wave(
+        mut self,
+        channel: UwbChannel,
+    ) -> Result<DW1000<SPI, TestMode>, Error<SPI>> {
+        self.force_idle(false)?;
+        let saved = self.enter_test_mode()?;
+
+        // Configure the PLL and the TX blocks for the channel
+        self.ll
+            .fs_pllcfg()
+            .write(|w| w.value(channel.get_recommended_fs_pllcfg()))?;
+        self.ll
+            .fs_plltune()
+            .write(|w| w.value(channel.get_recommended_fs_plltune()))?;
+        self.ll
+            .rf_txctrl()
+            .write(|w| w.value(channel.get_recommended_rf_txctrl()))?;
+
+        self.enable_tx_blocks()?;
+
+        // Run the system and TX clocks from the PLL
+        self.ll
+            .pmsc_ctrl0()
+            .modify(|r, w| w.raw_value(r.raw_value() & !0xffff | 0x0722))?;
+
+        // Disable fine grain TX sequencing and enable CW mode
+        self.ll.pmsc_txfseq().write(|w| w.value(0))?;
+        self.ll.tc_pgtest().write(|w| w.value(0x13))?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: saved,
+        })
+    }
+
+    /// Starts sending a frame repeatedly
+    ///
+    /// This is intended for RF tests, like regulatory pre-compliance or
+    /// production tests. `frame` is sent as-is, followed by the CRC, if
+    /// configured in `config`. A new transmission is started every `period`,
+    /// which is rounded down to a multiple of 512 DW1000 time units (about
+    /// 8 ns). Periods shorter than 4 of those multiples are extended to that
+    /// minimum.
+    ///
+    /// The frame is repeated until [`DW1000::stop`] is called.
+    pub fn start_continuous_frame(
+        mut self,
+        frame: &[u8],
+        period: Duration,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, TestMode>, Error<SPI>> {
+        let max_len = config.phr_mode.max_frame_len();
+        self.prepare_transmission(
+            |buffer| {
+                let buffer = buffer
+                    .get_mut(..frame.len())
+                    .ok_or(Error::FrameTooLong { max_len })?;
+                buffer.copy_from_slice(frame);
+                Ok(frame.len())
+            },
+            &SendTime::Now,
+            &config,
+        )?;
+
+        let saved = self.enter_test_mode()?;
+        self.enable_tx_blocks()?;
+
+        // Run the system and TX clocks from the PLL
+        self.ll
+            .pmsc_ctrl0()
+            .modify(|_, w| w.sysclks(0b10).txclks(0b10))?;
+
+        let period = period.value().max(MIN_FRAME_PERIOD) / 512;
+        self.ll.dx_time().write(|w| w.value(period))?;
+        self.ll.diag_tmc().write(|w| w.tx_pstm(1))?;
+
+        self.start_transmission(&SendTime::Now, config.append_crc, false)?;
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: saved,
+        })
+    }
+
+    /// Saves the registers changed by the test modes, and disables the
+    /// sequencing of the RF blocks
+    fn enter_test_mode(&mut self) -> Result<TestMode, Error<SPI>> {
+        let pmsc_ctrl0 = self.ll.pmsc_ctrl0().read()?.raw_value();
+        let pmsc_ctrl1 = self.ll.pmsc_ctrl1().read()?.raw_value();
+        let pmsc_txfseq = self.ll.pmsc_txfseq().read()?.value();
+
+        self.ll.pmsc_ctrl0().modify(|_, w| w.sysclks(0b01))?;
+        // Clear the lower 16 bits, including PKTSEQ
+        self.ll
+            .pmsc_ctrl1()
+            .modify(|r, w| w.raw_value(r.raw_value() & !0xffff))?;
+
+        Ok(TestMode {
+            pmsc_ctrl0,
+            pmsc_ctrl1,
+            pmsc_txfseq,
+        })
+    }
+
+    /// Forces the PLL and the TX blocks on
+    ///
+    /// Enables the TX, PLL and LDO blocks (RF_CONF = 0x001FFF00), then forces
+    /// the TX/RX switch to TX (RF_CONF = 0x005FFF00).
+    fn enable_tx_blocks(&mut self) -> Result<(), Error<SPI>> {
+        self.ll
+            .rf_conf()
+            .write(|w| w.txfen(0x1f).pllfen(0b111).ldofen(0x1f))?;
+        self.ll
+            .rf_conf()
+            .write(|w| w.txfen(0x1f).pllfen(0b111).ldofen(0x1f).txrxsw(0b10))?;
+
+        Ok(())
+    }
+}
+
+impl<SPI> DW1000<SPI, TestMode>
+where
+    SPI: SpiDevice,
+{
+    /// Stops the test mode and returns to the `Ready` state
+    #[allow(clippy::type_complexity)]
+    pub fn stop(mut self) -> Result<DW1000<SPI, Ready>, (Self, Error<SPI>)> {
+        match self.leave_test_mode() {
+            Ok(()) => {}
+            Err(error) => return Err((self, error)),
+        }
+
+        Ok(DW1000 {
+            ll: self.ll,
+            seq: self.seq,
+            state: Ready,
+        })
+    }
+
+    fn leave_test_mode(&mut self) -> Result<(), Error<SPI>> {
+        self.ll.tc_pgtest().write(|w| w.value(0x00))?;
+        self.ll.diag_tmc().write(|w| w.tx_pstm(0))?;
+        self.force_idle(false)?;
+        self.reset_tx_flags()?;
+
+        // Hand control of the RF blocks back to the sequencer
+        self.ll.rf_conf().write(|w| w)?;
+
+        let TestMode {
+            pmsc_ctrl0,
+            pmsc_ctrl1,
+            pmsc_txfseq,
+        } = self.state;
+        self.ll.pmsc_txfseq().write(|w| w.value(pmsc_txfseq))?;
+        self.ll.pmsc_ctrl1().write(|w| w.raw_value(pmsc_ctrl1))?;
+        self.ll.pmsc_ctrl0().write(|w| w.raw_value(pmsc_ctrl0))?;
+
+        Ok(())
+    }
+}
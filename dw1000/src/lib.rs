@@ -15,6 +15,11 @@
 //! microcontroller used on the DWM1001 module), so be aware that you might run
 //! into problems on other devices.
 //!
+//! If the `async` feature is enabled, an async variant of the high-level
+//! interface is available in `hl::asynch`. It is built on
+//! `embedded-hal-async` and waits for the DW1000's IRQ output instead of
+//! polling.
+//!
 //! [high-level interface]: hl/index.html
 //! [register-level interface]: ll/index.html
 //! [`dwm1001`]: https://crates.io/crates/dwm1001
@@ -24,9 +29,12 @@
 #![no_std]
 #![deny(missing_docs)]
 
+pub mod calibration;
 pub mod configs;
 pub mod hl;
 pub mod ll;
+mod math;
+pub mod positioning;
 pub mod range_bias;
 pub mod ranging;
 pub mod time;
@@ -35,8 +43,8 @@ pub mod time;
 pub use ieee802154::mac;
 
 pub use crate::hl::{
-    AutoDoubleBufferReceiving, Error, Message, Ready, Sending, SingleBufferReceiving, Sleeping,
-    Uninitialized, DW1000,
+    AutoDoubleBufferReceiving, AwaitingAck, Error, Message, Ready, Sending, SendingWithResponse,
+    SingleBufferReceiving, Sleeping, SleepingReceiveOnWake, TestMode, Uninitialized, DW1000,
 };
 
 pub use crate::configs::{RxConfig, TxConfig};
@@ -17,7 +17,7 @@
 
 use core::marker::PhantomData;
 
-use embedded_hal::spi::{Operation, SpiDevice};
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
 
 /// Entry point to the DW1000 driver's low-level API
 ///
@@ -28,7 +28,7 @@ pub struct DW1000<SPI> {
     spi: SPI,
 }
 
-impl<SPI: SpiDevice> DW1000<SPI> {
+impl<SPI> DW1000<SPI> {
     /// Create a new instance of `DW1000`
     ///
     /// Requires the SPI peripheral and the chip select pin that are connected
@@ -36,7 +36,9 @@ impl<SPI: SpiDevice> DW1000<SPI> {
     pub fn new(spi: SPI) -> Self {
         DW1000 { spi }
     }
+}
 
+impl<SPI: SpiDevice> DW1000<SPI> {
     /// Read a whole block of data
     ///
     /// The buffer must have the first 3 bytes free so the protocol header can be put there.
@@ -75,6 +77,28 @@ impl<SPI: SpiDevice> DW1000<SPI> {
         Ok(&mut block[1..])
     }
 
+    /// Writes data into the transmit data buffer, starting at `offset`
+    ///
+    /// Unlike [`DW1000::tx_buffer`], this gives access to the full 1024 bytes
+    /// of the buffer, as required for frames with a non-standard PHR.
+    pub fn write_tx_data(&mut self, offset: u16, data: &[u8]) -> Result<(), Error<SPI>> {
+        let header = buffer_header(true, 0x09, offset);
+        self.spi
+            .transaction(&mut [Operation::Write(&header), Operation::Write(data)])
+            .map_err(Error)
+    }
+
+    /// Reads data from the receive data buffer, starting at `offset`
+    ///
+    /// Unlike [`DW1000::rx_buffer`], this gives access to the full 1024 bytes
+    /// of the buffer, as required for frames with a non-standard PHR.
+    pub fn read_rx_data(&mut self, offset: u16, buffer: &mut [u8]) -> Result<(), Error<SPI>> {
+        let header = buffer_header(false, 0x11, offset);
+        self.spi
+            .transaction(&mut [Operation::Write(&header), Operation::Read(buffer)])
+            .map_err(Error)
+    }
+
     /// Allows for an access to the spi type.
     /// This can be used to change the speed.
     ///
@@ -168,10 +192,109 @@ where
     }
 }
 
+#[cfg(feature = "async")]
+impl<'s, R, SPI> RegAccessor<'s, R, SPI>
+where
+    SPI: embedded_hal_async::spi::SpiDevice,
+{
+    /// Read from the register, using the async SPI interface
+    pub async fn read_async(&mut self) -> Result<R::Read, Error<SPI>>
+    where
+        R: Register + Readable,
+    {
+        let mut r = R::read();
+        let buffer = R::buffer(&mut r);
+
+        init_header::<R>(false, buffer);
+
+        self.0.spi.transfer_in_place(buffer).await.map_err(Error)?;
+
+        Ok(r)
+    }
+
+    /// Write to the register, using the async SPI interface
+    pub async fn write_async<F>(&mut self, f: F) -> Result<(), Error<SPI>>
+    where
+        R: Register + Writable,
+        F: FnOnce(&mut R::Write) -> &mut R::Write,
+    {
+        let mut w = R::write();
+        f(&mut w);
+
+        let buffer = R::buffer(&mut w);
+        init_header::<R>(true, buffer);
+
+        self.0.spi.write(buffer).await.map_err(Error)?;
+
+        Ok(())
+    }
+
+    /// Modify the register, using the async SPI interface
+    pub async fn modify_async<F>(&mut self, f: F) -> Result<(), Error<SPI>>
+    where
+        R: Register + Readable + Writable,
+        F: for<'r> FnOnce(&mut R::Read, &'r mut R::Write) -> &'r mut R::Write,
+    {
+        let mut r = self.read_async().await?;
+        let mut w = R::write();
+
+        <R as Writable>::buffer(&mut w).copy_from_slice(<R as Readable>::buffer(&mut r));
+
+        f(&mut r, &mut w);
+
+        let buffer = <R as Writable>::buffer(&mut w);
+        init_header::<R>(true, buffer);
+
+        self.0.spi.write(buffer).await.map_err(Error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<SPI: embedded_hal_async::spi::SpiDevice> DW1000<SPI> {
+    /// Writes data into the transmit data buffer, using the async SPI
+    /// interface
+    ///
+    /// See [`DW1000::write_tx_data`].
+    pub async fn write_tx_data_async(
+        &mut self,
+        offset: u16,
+        data: &[u8],
+    ) -> Result<(), Error<SPI>> {
+        let header = buffer_header(true, 0x09, offset);
+        self.spi
+            .transaction(&mut [
+                embedded_hal_async::spi::Operation::Write(&header),
+                embedded_hal_async::spi::Operation::Write(data),
+            ])
+            .await
+            .map_err(Error)
+    }
+
+    /// Reads data from the receive data buffer, using the async SPI interface
+    ///
+    /// See [`DW1000::read_rx_data`].
+    pub async fn read_rx_data_async(
+        &mut self,
+        offset: u16,
+        buffer: &mut [u8],
+    ) -> Result<(), Error<SPI>> {
+        let header = buffer_header(false, 0x11, offset);
+        self.spi
+            .transaction(&mut [
+                embedded_hal_async::spi::Operation::Write(&header),
+                embedded_hal_async::spi::Operation::Read(buffer),
+            ])
+            .await
+            .map_err(Error)
+    }
+}
+
 /// An SPI error that can occur when communicating with the DW1000
 #[derive(Debug)]
 #[repr(transparent)]
-pub struct Error<SPI: SpiDevice>(pub SPI::Error);
+pub struct Error<SPI: ErrorType>(pub SPI::Error);
 
 /// Initializes the SPI message header
 ///
@@ -200,6 +323,15 @@ fn init_header<R: Register>(write: bool, buffer: &mut [u8]) -> usize {
     3
 }
 
+/// Builds the 3-byte SPI message header for accessing a buffer at an offset
+fn buffer_header(write: bool, id: u8, offset: u16) -> [u8; 3] {
+    [
+        (((write as u8) << 7) & 0x80) | 0x40 | (id & 0x3f),
+        0x80 | (offset & 0x7f) as u8,   // lower 7 bits (of 15)
+        ((offset & 0x7f80) >> 7) as u8, // higher 8 bits (of 15)
+    ]
+}
+
 /// Implemented for all registers
 ///
 /// This is a mostly internal crate that should not be implemented or used
@@ -658,6 +790,9 @@ impl_register! {
     0x0A, 0x00, 5, RW, DX_TIME(dx_time) { /// Delayed Send or Receive Time
         value, 0, 39, u64; /// Delayed Send or Receive Time
     }
+    0x0C, 0x00, 2, RW, RX_FWTO(rx_fwto) { /// Receive Frame Wait Timeout Period
+        value, 0, 15, u16; /// Receive Frame Wait Timeout Period
+    }
     0x0D, 0x00, 4, RW, SYS_CTRL(sys_ctrl) { /// System Control Register
         sfcst,      0,  0, u8; /// Suppress Auto-FCS Transmission
         txstrt,     1,  1, u8; /// Transmit Start
@@ -777,6 +912,14 @@ impl_register! {
         rx_state,    8, 12, u8; /// Current Receive State Machine value
         pmsc_state, 16, 23, u8; /// Current PMSC State Machine value
     }
+    0x1A, 0x00, 4, RW, ACK_RESP_T(ack_resp_t) { /// Acknowledgement Time and Response Time
+        w4r_tim,  0, 19, u32; /// Wait-for-Response turn-around Time
+        ack_tim, 24, 31, u8;  /// Auto-Acknowledgement turn-around Time
+    }
+    0x1D, 0x00, 4, RW, RX_SNIFF(rx_sniff) { /// Sniff Mode
+        sniff_ont,   0,  3, u8; /// SNIFF Mode ON time
+        sniff_offt,  8, 15, u8; /// SNIFF Mode OFF time
+    }
     0x1E, 0x00, 4, RW, TX_POWER(tx_power) { /// TX Power Control
         // The TX_POWER register has multiple sets of fields defined, depending
         // on the smart TX power control setting. I don't know how to model
@@ -965,12 +1108,19 @@ impl_register! {
     0x27, 0x26, 2, RW, DRX_TUNE4H(drx_tune4h) { /// Digital Tuning Register 4h
         value, 0, 15, u16; /// DRX_TUNE4H tuning value
     }
-    0x27, 0x28, 2, RO, DRX_CAR_INT(dxr_car_int) { /// Carrier Recovery Integrator Register
-        value, 0, 15, u16; /// value
+    0x27, 0x28, 3, RO, DRX_CAR_INT(dxr_car_int) { /// Carrier Recovery Integrator Register
+        value, 0, 20, u32; /// value (21-bit signed int)
     }
     0x27, 0x2C, 2, RO, RXPACC_NOSAT(rxpacc_nosat) { /// Digital debug register. Unsaturated accumulated preamble symbols.
         value, 0, 15, u16; /// value
     }
+    0x28, 0x00, 4, RW, RF_CONF(rf_conf) { /// RF Configuration Register
+        txfen,   8, 12, u8; /// Transmit block force enable
+        pllfen, 13, 15, u8; /// PLL block force enables
+        ldofen, 16, 20, u8; /// Write 0x1F to force the enable to all LDOs
+        txrxsw, 21, 22, u8; /// Force TX/RX switch
+        value,   0, 31, u32; /// The entire register
+    }
     0x28, 0x0B, 1, RW, RF_RXCTRLH(rf_rxctrlh) { /// Analog RX Control Register
         value, 0, 7, u8; /// Analog RX Control Register
     }
@@ -979,6 +1129,12 @@ impl_register! {
         txmq,    9, 11, u8; /// Transmit mixer Q-factor tuning register
         value, 0, 23, u32; /// The entire register
     }
+    0x28, 0x11, 1, RW, RF_SENSOR_BIAS(rf_sensor_bias) { /// Analog bias control for the temperature and voltage sensors
+        value, 0, 7, u8; /// Undocumented. See the temperature and voltage measurement sequence in the user manual.
+    }
+    0x28, 0x12, 1, RW, RF_SENSOR_CTRL(rf_sensor_ctrl) { /// Analog bias and output control for the SAR ADC
+        value, 0, 7, u8; /// Undocumented. See the temperature and voltage measurement sequence in the user manual.
+    }
     0x28, 0x2C, 4, RO, RF_STATUS(rf_status) { /// RF Status Register
         cplllock,  0, 0, u8; /// Clock PLL lock status
         cplllow,   1, 1, u8; /// Clock PLL low flag
@@ -988,15 +1144,33 @@ impl_register! {
     0x28, 0x30, 5, RW, LDOTUNE(ldotune) { /// LDO voltage tuning parameter
         value, 0, 39, u64; /// Internal LDO voltage tuning parameter
     }
+    0x2A, 0x00, 2, RW, TC_SARC(tc_sarc) { /// Transmitter Calibration - SAR Control
+        sar_ctrl, 0, 0, u8; /// Writing 1 starts the SAR ADC, writing 0 stops it
+    }
+    0x2A, 0x03, 3, RO, TC_SARL(tc_sarl) { /// Transmitter Calibration - Latest SAR Readings
+        sar_lvbat,  0,  7, u8; /// Latest SAR reading for Voltage level
+        sar_ltemp,  8, 15, u8; /// Latest SAR reading for Temperature level
+    }
+    0x2A, 0x06, 2, RO, TC_SARW(tc_sarw) { /// Transmitter Calibration - SAR Readings at Last Wake-Up
+        sar_wvbat,  0,  7, u8; /// SAR reading of Voltage level taken at last wake-up event
+        sar_wtemp,  8, 15, u8; /// SAR reading of Temperature level taken at last wake-up event
+    }
     0x2A, 0x0B, 1, RW, TC_PGDELAY(tc_pgdelay) { /// Pulse Generator Delay
         value, 0, 7, u8; /// Transmitter Calibration - Pulse Generator Delay
     }
+    0x2A, 0x0C, 1, RW, TC_PGTEST(tc_pgtest) { /// Transmitter Calibration - Pulse Generator Test
+        value, 0, 7, u8; /// 0x13 for continuous wave mode, 0x00 for normal operation
+    }
     0x2B, 0x07, 4, RW, FS_PLLCFG(fs_pllcfg) { /// Frequency synth - PLL configuration
         value, 0, 31, u32; /// Frequency synth - PLL configuration
     }
     0x2B, 0x0B, 1, RW, FS_PLLTUNE(fs_plltune) { /// Frequency synth - PLL Tuning
         value, 0, 7, u8; /// Frequency synthesiser - PLL Tuning
     }
+    0x2B, 0x0E, 1, RW, FS_XTALT(fs_xtalt) { /// Frequency synth - Crystal trim
+        xtalt,    0, 4, u8; /// Crystal Trim
+        reserved, 5, 7, u8; /// Reserved. Must be written as 0b011.
+    }
     0x2C, 0x00, 2, RW, AON_WCFG(aon_wcfg) { /// AON Wakeup Configuration Register
         onw_radc,  0,  0, u8; /// On Wake-up Run the (temperature and voltage) Analog-to-Digital Convertors.
         onw_rx,    1,  1, u8; /// On Wake-up turn on the Receiver.
@@ -1014,6 +1188,12 @@ impl_register! {
         dca_read, 3, 3, u8; /// Direct AON memory access read.
         dca_enab, 7, 7, u8; /// Direct AON memory access enable bit.
     }
+    0x2C, 0x03, 1, RO, AON_RDAT(aon_rdat) { /// AON Direct Access Read Data Result
+        value, 0, 7, u8; /// The data read from the AON memory address in AON_ADDR
+    }
+    0x2C, 0x04, 1, RW, AON_ADDR(aon_addr) { /// AON Direct Access Address
+        value, 0, 7, u8; /// The AON memory address to read from
+    }
     0x2C, 0x06, 4, RW, AON_CFG0(aon_cfg0) { /// AON Configuration Register 0
         sleep_en, 0, 0, u8; /// Sleep enable configuration bit
         wake_pin, 1, 1, u8; /// Wake using WAKEUP pin
@@ -1028,6 +1208,9 @@ impl_register! {
         smxx, 1, 1, u8; /// Thisbit needs to be set to 0 for correct operation in the SLEEP state within the DW1000.
         lposc_cal, 2, 2, u8; /// This bit enables the calibration function that measures the period of the IC’s internal low powered oscillator.
     }
+    0x2D, 0x00, 4, RW, OTP_WDAT(otp_wdat) { /// OTP Write Data
+        value, 0, 31, u32; /// OTP Write Data
+    }
     0x2D, 0x04, 2, RW, OTP_ADDR(otp_addr) { /// OTP Address
         value, 0, 10, u16; /// OTP Address
     }
@@ -1038,6 +1221,11 @@ impl_register! {
         otpprog,  6,  6, u8; /// Write OTP_WDAT to OTP_ADDR
         otpmr,    7, 10, u8; /// OTP mode register
         ldeload, 15, 15, u8; /// Force load of LDE microcode
+        raw_value, 0, 15, u16; /// The raw register value
+    }
+    0x2D, 0x08, 2, RW, OTP_STAT(otp_stat) { /// OTP Status
+        otpprgd, 0, 0, u8; /// OTP Programming Done
+        otpvpok, 1, 1, u8; /// OTP Programming Voltage OK
     }
     0x2D, 0x0A, 4, RO, OTP_RDAT(otp_rdat) { /// OTP Read Data
         value, 0, 31, u32; /// OTP Read Data
@@ -1071,6 +1259,9 @@ impl_register! {
     0x2F, 0x1A, 2, RO, EVC_TPW(evc_tpw) { /// TX Power-Up Warning Counter
         value, 0, 11, u16; /// TX Power-Up Warning Event Counter
     }
+    0x2F, 0x24, 2, RW, DIAG_TMC(diag_tmc) { /// Test Mode Control Register
+        tx_pstm, 4, 4, u8; /// Transmit Power Spectrum Test Mode
+    }
     0x36, 0x00, 4, RW, PMSC_CTRL0(pmsc_ctrl0) { /// PMSC Control Register 0
         sysclks,    0,  1, u8; /// System Clock Selection
         rxclks,     2,  3, u8; /// Receiver Clock Selection
@@ -1083,6 +1274,7 @@ impl_register! {
         gpdce,     18, 18, u8; /// GPIO De-bounce Clock Enable
         gpdrn,     19, 19, u8; /// GPIO De-bounce Reset (Not), active low
         khzclken,  23, 23, u8; /// Kilohertz Clock Enable
+        pll2_seq_en, 24, 24, u8; /// Enable PLL2 on/off sequencing by SNIFF mode
         softreset, 28, 31, u8; /// Soft Reset
         raw_value,  0, 31,u32; /// The raw register value
     }
@@ -1096,6 +1288,10 @@ impl_register! {
         pllsyn,    15, 15, u8; /// Enable clock used for external sync modes
         lderune,   17, 17, u8; /// LDE Run Enable
         khzclkdiv, 26, 31, u8; /// Kilohertz Clock Divisor
+        raw_value,  0, 31,u32; /// The raw register value
+    }
+    0x36, 0x26, 2, RW, PMSC_TXFSEQ(pmsc_txfseq) { /// PMSC fine grain TX sequencing control
+        value, 0, 15, u16; /// PMSC fine grain TX sequencing control
     }
     0x36, 0x28, 4, RW, PMSC_LEDC(pmsc_ledc) { /// PMSC LED Control Register
         blink_tim, 0, 7, u8; /// Blink time count value
@@ -1107,7 +1303,8 @@ impl_register! {
 /// Transmit Data Buffer
 ///
 /// Currently only the first 127 bytes of the buffer are supported, which is
-/// enough to support standard Standard IEEE 802.15.4 UWB frames.
+/// enough to support standard Standard IEEE 802.15.4 UWB frames. Use
+/// [`DW1000::write_tx_data`] to access the whole buffer.
 #[allow(non_camel_case_types)]
 pub struct TX_BUFFER;
 
@@ -1152,7 +1349,8 @@ pub mod tx_buffer {
 /// Receive Data Buffer
 ///
 /// Currently only the first 127 bytes of the buffer are supported, which is
-/// enough to support standard Standard IEEE 802.15.4 UWB frames.
+/// enough to support standard Standard IEEE 802.15.4 UWB frames. Use
+/// [`DW1000::read_rx_data`] to access the whole buffer.
 #[allow(non_camel_case_types)]
 pub struct RX_BUFFER;
 
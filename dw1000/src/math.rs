@@ -0,0 +1,89 @@
+//! Numeric helpers shared by the calibration, positioning and ranging code
+
+/// Solves `a x = b` using Gaussian elimination with partial pivoting
+///
+/// Returns `None`, if `a` is (close to) singular, meaning a pivot is smaller
+/// than `tolerance` times the largest element of `a`. Comparing against the
+/// largest element keeps the check independent of the unit.
+pub(crate) fn solve_linear<const N: usize>(
+    mut a: [[f64; N]; N],
+    mut b: [f64; N],
+    tolerance: f64,
+) -> Option<[f64; N]> {
+    let scale = a
+        .iter()
+        .flat_map(|row| row.iter())
+        .fold(0.0, |max: f64, &x| max.max(abs(x)));
+    if scale == 0.0 {
+        return None;
+    }
+
+    for col in 0..N {
+        let pivot = (col..N).max_by(|&i, &j| abs(a[i][col]).total_cmp(&abs(a[j][col])))?;
+        if abs(a[pivot][col]) < scale * tolerance {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let pivot_row = a[col];
+        for row in col + 1..N {
+            let factor = a[row][col] / pivot_row[col];
+            for (x, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let mut sum = b[row];
+        for k in row + 1..N {
+            sum -= a[row][k] * x[k];
+        }
+        x[row] = sum / a[row][row];
+    }
+
+    Some(x)
+}
+
+/// Rounds to the nearest integer
+pub(crate) fn round(value: f64) -> i64 {
+    if value >= 0.0 {
+        (value + 0.5) as i64
+    } else {
+        (value - 0.5) as i64
+    }
+}
+
+fn abs(x: f64) -> f64 {
+    if x < 0.0 {
+        -x
+    } else {
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solve_linear_should_solve_and_detect_singular_matrices() {
+        let x = solve_linear([[0.0, 2.0], [4.0, 1.0]], [4.0, 6.0], 1e-9).unwrap();
+        assert!((x[0] - 1.0).abs() < 1e-12 && (x[1] - 2.0).abs() < 1e-12);
+
+        assert_eq!(
+            solve_linear([[1.0, 2.0], [2.0, 4.0]], [1.0, 2.0], 1e-9),
+            None
+        );
+    }
+
+    #[test]
+    fn round_should_round_half_away_from_zero() {
+        assert_eq!(round(1.5), 2);
+        assert_eq!(round(-1.5), -2);
+        assert_eq!(round(-1.4), -1);
+    }
+}
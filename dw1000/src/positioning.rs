@@ -0,0 +1,514 @@
+//! Position estimation from range or TDoA measurements
+//!
+//! This module estimates the position of a node from its distances to a number
+//! of anchors with known positions (see [`solve_ranges`]), or from the
+//! differences between the distances to the anchors and the distance to a
+//! reference anchor, as measured in a TDoA system (see [`solve_tdoa`]).
+//!
+//! Both work in two and three dimensions, depending on the length `D` of the
+//! position arrays. The unit of positions and distances can be chosen freely,
+//! as long as it is used consistently.
+//!
+//! The position is estimated using a least-squares fit (Gauss-Newton). If there
+//! are more measurements than needed, measurements whose residual exceeds
+//! [`SolverConfig::outlier_threshold`] are rejected one at a time, starting
+//! with the worst one.
+
+use crate::math;
+
+/// The maximum number of measurements that can be passed to the solvers
+pub const MAX_MEASUREMENTS: usize = 32;
+
+/// The distance to an anchor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RangeMeasurement<const D: usize> {
+    /// The position of the anchor
+    pub anchor: [f32; D],
+
+    /// The measured distance to the anchor
+    pub distance: f32,
+}
+
+/// The difference of the distances to an anchor and to the reference anchor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TdoaMeasurement<const D: usize> {
+    /// The position of the anchor
+    pub anchor: [f32; D],
+
+    /// The distance to the anchor minus the distance to the reference anchor
+    ///
+    /// This is the difference of the arrival times at the two anchors,
+    /// multiplied by the speed of light.
+    pub distance_difference: f32,
+}
+
+/// Configuration of the position solvers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolverConfig {
+    /// The maximum number of Gauss-Newton iterations
+    pub max_iterations: u32,
+
+    /// The iteration stops once a step is shorter than this
+    pub tolerance: f32,
+
+    /// Measurements with a larger absolute residual are rejected as outliers
+    ///
+    /// Measurements are only rejected as long as there are more than enough
+    /// measurements left to estimate the position.
+    pub outlier_threshold: f32,
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        SolverConfig {
+            max_iterations: 20,
+            tolerance: 1e-4,
+            outlier_threshold: 0.5,
+        }
+    }
+}
+
+/// An estimated position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Solution<const D: usize> {
+    /// The estimated position
+    pub position: [f32; D],
+
+    /// The residual of each measurement at the estimated position
+    ///
+    /// This is the value predicted from the position minus the measured value.
+    ///
+    /// Only the first entries, up to the number of measurements, are used.
+    /// This includes the measurements that were rejected as outliers.
+    pub residuals: [f32; MAX_MEASUREMENTS],
+
+    /// The root mean square of the residuals of the measurements that were
+    /// used
+    pub rms_residual: f32,
+
+    /// The geometric dilution of precision of the measurements that were used
+    ///
+    /// This describes how much the geometry of the anchors amplifies the
+    /// measurement errors. Values below about 2 are good, large values mean
+    /// the estimated position is unreliable.
+    pub gdop: f32,
+
+    /// Bit mask of the measurements that were rejected as outliers
+    pub outliers: u32,
+}
+
+impl<const D: usize> Solution<D> {
+    /// Indicates whether the measurement with the given index was rejected
+    pub fn is_outlier(&self, index: usize) -> bool {
+        index < MAX_MEASUREMENTS && self.outliers & (1 << index) != 0
+    }
+}
+
+/// Returned from the solvers in case of an error
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PositioningError {
+    /// Not enough measurements to estimate a position
+    NotEnoughMeasurements,
+
+    /// More than [`MAX_MEASUREMENTS`] measurements
+    TooManyMeasurements,
+
+    /// The anchor geometry doesn't allow estimating a position
+    ///
+    /// This happens for example, if all anchors are on a line.
+    SingularGeometry,
+
+    /// The solver didn't converge within the maximum number of iterations
+    NotConverged,
+}
+
+/// Estimates a position from the distances to a number of anchors
+///
+/// At least `D` measurements are required. If `initial_guess` is `None`, the
+/// centroid of the anchors is used. If all anchors are in a plane (in 3D) or
+/// on a line (in 2D), the solution is ambiguous, and an initial guess on the
+/// correct side should be given.
+pub fn solve_ranges<const D: usize>(
+    measurements: &[RangeMeasurement<D>],
+    initial_guess: Option<[f32; D]>,
+    config: &SolverConfig,
+) -> Result<Solution<D>, PositioningError> {
+    let initial_guess =
+        initial_guess.unwrap_or_else(|| centroid(measurements.iter().map(|m| m.anchor)));
+
+    solve(
+        measurements.len(),
+        initial_guess,
+        config,
+        |index, position| {
+            let measurement = &measurements[index];
+            let (distance, direction) = distance_and_direction(position, &measurement.anchor);
+            (distance - measurement.distance, direction)
+        },
+    )
+}
+
+/// Estimates a position from TDoA measurements
+///
+/// The distance differences of the measurements are relative to the anchor at
+/// `reference`. At least `D` measurements are required.
+///
+/// See [`solve_ranges`] for `initial_guess`.
+pub fn solve_tdoa<const D: usize>(
+    reference: [f32; D],
+    measurements: &[TdoaMeasurement<D>],
+    initial_guess: Option<[f32; D]>,
+    config: &SolverConfig,
+) -> Result<Solution<D>, PositioningError> {
+    let initial_guess = initial_guess.unwrap_or_else(|| {
+        centroid(core::iter::once(reference).chain(measurements.iter().map(|m| m.anchor)))
+    });
+
+    solve(
+        measurements.len(),
+        initial_guess,
+        config,
+        |index, position| {
+            let measurement = &measurements[index];
+            let (distance, direction) = distance_and_direction(position, &measurement.anchor);
+            let (reference_distance, reference_direction) =
+                distance_and_direction(position, &reference);
+
+            let mut gradient = [0.0; D];
+            for i in 0..D {
+                gradient[i] = direction[i] - reference_direction[i];
+            }
+
+            (
+                distance - reference_distance - measurement.distance_difference,
+                gradient,
+            )
+        },
+    )
+}
+
+/// Solves the least-squares problem and rejects outliers
+///
+/// `residual` returns the residual of a measurement at a position, and its
+/// gradient.
+fn solve<const D: usize>(
+    count: usize,
+    initial_guess: [f32; D],
+    config: &SolverConfig,
+    residual: impl Fn(usize, &[f32; D]) -> (f32, [f32; D]),
+) -> Result<Solution<D>, PositioningError> {
+    if count < D || count == 0 {
+        return Err(PositioningError::NotEnoughMeasurements);
+    }
+    if count > MAX_MEASUREMENTS {
+        return Err(PositioningError::TooManyMeasurements);
+    }
+
+    let mut outliers = 0u32;
+    let mut position = initial_guess;
+
+    loop {
+        position = gauss_newton(count, outliers, position, config, &residual)?;
+
+        // Find the worst measurement that is still in use
+        let mut worst: Option<(usize, f32)> = None;
+        for index in (0..count).filter(|&index| outliers & (1 << index) == 0) {
+            let (r, _) = residual(index, &position);
+            if worst.map_or(true, |(_, worst)| abs(r) > worst) {
+                worst = Some((index, abs(r)));
+            }
+        }
+
+        let used = count - outliers.count_ones() as usize;
+        match worst {
+            // Only reject, if there's still redundancy afterwards
+            Some((index, r)) if r > config.outlier_threshold && used > D + 1 => {
+                outliers |= 1 << index;
+            }
+            _ => break,
+        }
+    }
+
+    let mut residuals = [0.0; MAX_MEASUREMENTS];
+    let mut sum_of_squares = 0.0;
+    let mut normal = [[0.0; D]; D];
+    for (index, slot) in residuals.iter_mut().enumerate().take(count) {
+        let (r, gradient) = residual(index, &position);
+        *slot = r;
+
+        if outliers & (1 << index) == 0 {
+            sum_of_squares += r * r;
+            add_outer_product(&mut normal, &gradient);
+        }
+    }
+    let used = (count - outliers.count_ones() as usize) as f32;
+
+    // The diagonal of the inverse of the normal matrix
+    let mut trace = 0.0;
+    for i in 0..D {
+        let mut unit = [0.0; D];
+        unit[i] = 1.0;
+        trace += solve_linear(normal, unit).ok_or(PositioningError::SingularGeometry)?[i];
+    }
+
+    Ok(Solution {
+        position,
+        residuals,
+        rms_residual: sqrt(sum_of_squares / used),
+        gdop: sqrt(trace),
+        outliers,
+    })
+}
+
+/// Runs Gauss-Newton iterations on the measurements not marked as outliers
+fn gauss_newton<const D: usize>(
+    count: usize,
+    outliers: u32,
+    mut position: [f32; D],
+    config: &SolverConfig,
+    residual: &impl Fn(usize, &[f32; D]) -> (f32, [f32; D]),
+) -> Result<[f32; D], PositioningError> {
+    for _ in 0..config.max_iterations {
+        // Build the normal equations: (J^T J) step = -J^T r
+        let mut normal = [[0.0; D]; D];
+        let mut rhs = [0.0; D];
+        for index in (0..count).filter(|&index| outliers & (1 << index) == 0) {
+            let (r, gradient) = residual(index, &position);
+            add_outer_product(&mut normal, &gradient);
+            for i in 0..D {
+                rhs[i] -= gradient[i] * r;
+            }
+        }
+
+        let step = solve_linear(normal, rhs).ok_or(PositioningError::SingularGeometry)?;
+
+        let mut step_length = 0.0;
+        for i in 0..D {
+            position[i] += step[i];
+            step_length += step[i] * step[i];
+        }
+
+        if sqrt(step_length) < config.tolerance {
+            return Ok(position);
+        }
+    }
+
+    Err(PositioningError::NotConverged)
+}
+
+/// Adds `v v^T` to `matrix`
+fn add_outer_product<const D: usize>(matrix: &mut [[f32; D]; D], v: &[f32; D]) {
+    for i in 0..D {
+        for j in 0..D {
+            matrix[i][j] += v[i] * v[j];
+        }
+    }
+}
+
+/// Solves `a x = b`, returning `None`, if `a` is (close to) singular
+fn solve_linear<const D: usize>(a: [[f32; D]; D], b: [f32; D]) -> Option<[f32; D]> {
+    // The tolerance matches the precision of the `f32` inputs
+    let x = math::solve_linear(a.map(|row| row.map(f64::from)), b.map(f64::from), 1e-6)?;
+    Some(x.map(|x| x as f32))
+}
+
+/// Returns the distance between `position` and `anchor`, and the unit vector
+/// pointing from `anchor` to `position`
+fn distance_and_direction<const D: usize>(
+    position: &[f32; D],
+    anchor: &[f32; D],
+) -> (f32, [f32; D]) {
+    let mut delta = [0.0; D];
+    let mut sum_of_squares = 0.0;
+    for i in 0..D {
+        delta[i] = position[i] - anchor[i];
+        sum_of_squares += delta[i] * delta[i];
+    }
+
+    let distance = sqrt(sum_of_squares);
+    if distance > 0.0 {
+        for d in &mut delta {
+            *d /= distance;
+        }
+    }
+
+    (distance, delta)
+}
+
+fn centroid<const D: usize>(points: impl Iterator<Item = [f32; D]>) -> [f32; D] {
+    let mut sum = [0.0; D];
+    let mut count = 0.0;
+    for point in points {
+        for i in 0..D {
+            sum[i] += point[i];
+        }
+        count += 1.0;
+    }
+
+    if count > 0.0 {
+        for s in &mut sum {
+            *s /= count;
+        }
+    }
+
+    sum
+}
+
+fn sqrt(x: f32) -> f32 {
+    #[allow(unused_imports)]
+    // Not used on x86, but used on mcu target due to f32 core lib sillyness.
+    use micromath::F32Ext;
+
+    x.sqrt()
+}
+
+fn abs(x: f32) -> f32 {
+    #[allow(unused_imports)]
+    // Not used on x86, but used on mcu target due to f32 core lib sillyness.
+    use micromath::F32Ext;
+
+    x.abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance<const D: usize>(a: &[f32; D], b: &[f32; D]) -> f32 {
+        distance_and_direction(a, b).0
+    }
+
+    fn ranges<const D: usize>(
+        anchors: &[[f32; D]],
+        position: &[f32; D],
+    ) -> [RangeMeasurement<D>; 8] {
+        let mut measurements = [RangeMeasurement {
+            anchor: [0.0; D],
+            distance: 0.0,
+        }; 8];
+        for (measurement, anchor) in measurements.iter_mut().zip(anchors) {
+            measurement.anchor = *anchor;
+            measurement.distance = distance(position, anchor);
+        }
+        measurements
+    }
+
+    fn assert_close<const D: usize>(actual: &[f32; D], expected: &[f32; D]) {
+        assert!(
+            distance(actual, expected) < 1e-3,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn ranges_2d() {
+        let anchors = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let position = [3.0, 7.0];
+        let measurements = ranges(&anchors, &position);
+
+        let solution = solve_ranges(&measurements[..4], None, &SolverConfig::default()).unwrap();
+
+        assert_close(&solution.position, &position);
+        assert!(solution.rms_residual < 1e-3);
+        assert_eq!(solution.outliers, 0);
+    }
+
+    #[test]
+    fn ranges_3d() {
+        let anchors = [
+            [0.0, 0.0, 3.0],
+            [10.0, 0.0, 2.5],
+            [10.0, 10.0, 3.0],
+            [0.0, 10.0, 2.0],
+            [5.0, 5.0, 0.0],
+        ];
+        let position = [4.0, 6.0, 1.0];
+        let measurements = ranges(&anchors, &position);
+
+        let solution = solve_ranges(&measurements[..5], None, &SolverConfig::default()).unwrap();
+
+        assert_close(&solution.position, &position);
+    }
+
+    #[test]
+    fn ranges_reject_outlier() {
+        let anchors = [
+            [0.0, 0.0],
+            [10.0, 0.0],
+            [10.0, 10.0],
+            [0.0, 10.0],
+            [5.0, -5.0],
+            [-5.0, 5.0],
+        ];
+        let position = [3.0, 7.0];
+        let mut measurements = ranges(&anchors, &position);
+        // Non-line-of-sight measurement
+        measurements[2].distance += 3.0;
+
+        let solution = solve_ranges(&measurements[..6], None, &SolverConfig::default()).unwrap();
+
+        assert_close(&solution.position, &position);
+        assert!(solution.is_outlier(2));
+        assert_eq!(solution.outliers.count_ones(), 1);
+        assert!((solution.residuals[2] + 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tdoa_2d() {
+        let reference = [0.0, 0.0];
+        let anchors = [[10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let position = [6.0, 2.0];
+
+        let mut measurements = [TdoaMeasurement {
+            anchor: [0.0; 2],
+            distance_difference: 0.0,
+        }; 3];
+        for (measurement, anchor) in measurements.iter_mut().zip(&anchors) {
+            measurement.anchor = *anchor;
+            measurement.distance_difference =
+                distance(&position, anchor) - distance(&position, &reference);
+        }
+
+        let solution =
+            solve_tdoa(reference, &measurements, None, &SolverConfig::default()).unwrap();
+
+        assert_close(&solution.position, &position);
+    }
+
+    #[test]
+    fn gdop_reflects_geometry() {
+        let position = [5.0, 5.0];
+        let good = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let bad = [[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [3.0, 0.5]];
+
+        let config = SolverConfig::default();
+        let good = solve_ranges(&ranges(&good, &position)[..4], None, &config).unwrap();
+        let bad = solve_ranges(&ranges(&bad, &position)[..4], Some([4.0, 4.0]), &config).unwrap();
+
+        assert!(good.gdop < 1.5, "{}", good.gdop);
+        assert!(bad.gdop > 2.0 * good.gdop, "{}", bad.gdop);
+    }
+
+    #[test]
+    fn collinear_anchors_are_singular() {
+        let anchors = [[0.0, 0.0], [5.0, 0.0], [10.0, 0.0]];
+        let measurements = ranges(&anchors, &[5.0, 5.0]);
+
+        assert_eq!(
+            solve_ranges(&measurements[..3], None, &SolverConfig::default()),
+            Err(PositioningError::SingularGeometry)
+        );
+    }
+
+    #[test]
+    fn not_enough_measurements() {
+        let measurements = ranges(&[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]], &[0.0; 3]);
+
+        assert_eq!(
+            solve_ranges(&measurements[..2], None, &SolverConfig::default()),
+            Err(PositioningError::NotEnoughMeasurements)
+        );
+    }
+}
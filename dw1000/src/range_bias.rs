@@ -1,6 +1,20 @@
-//! Partial implementation of the range bias as described in APS011 1.1
-
-use crate::configs::{PulseRepetitionFrequency, RxConfig, UwbChannel};
+//! Implementation of the range bias as described in APS011 1.1
+//!
+//! The distance measured by the DW1000 depends on the level of the received
+//! signal (RSL). This bias can be corrected with [`get_range_bias_cm`], or
+//! directly on a measured distance with [`correct_distance_mm`].
+//!
+//! APS011 provides one table per bandwidth and PRF. The 500 MHz tables apply
+//! to channels 1, 2, 3 and 5, the 900 MHz tables to channels 4 and 7.
+//!
+//! The RSL is not the same as the signal strength estimated by the DW1000
+//! ([`RxQuality::rssi`]), which underestimates strong signals. Use
+//! [`estimate_rsl`] to get the RSL from it.
+
+use crate::{
+    configs::{PulseRepetitionFrequency, RxConfig, UwbChannel},
+    hl::RxQuality,
+};
 
 /// The range bias table for PRF 16Mhz and a bandwidth of 500Mhz.
 ///
@@ -46,6 +60,9 @@ const RANGE_BIAS_CORRECTION_PRF64_MHZ900: [f32; 18] = [
 ];
 
 /// Get the range bias based on the rx rsl and the config the radio used to receive the message
+///
+/// The bias is interpolated linearly between the values of the table. Below
+/// and above the range of the table, the first and last value are used.
 pub fn get_range_bias_cm(rsl: f32, rx_config: &RxConfig) -> f32 {
     #[allow(unused_imports)]
     // Not used on x86, but used on mcu target due to f32 core lib sillyness.
@@ -56,10 +73,13 @@ pub fn get_range_bias_cm(rsl: f32, rx_config: &RxConfig) -> f32 {
     }
 
     // Determine the message characteristics
-    let low_bandwidth = !matches!(
-        rx_config.channel,
-        UwbChannel::Channel7 | UwbChannel::Channel4
-    );
+    let low_bandwidth = match rx_config.channel {
+        UwbChannel::Channel1
+        | UwbChannel::Channel2
+        | UwbChannel::Channel3
+        | UwbChannel::Channel5 => true,
+        UwbChannel::Channel4 | UwbChannel::Channel7 => false,
+    };
     let low_prf = match rx_config.pulse_repetition_frequency {
         PulseRepetitionFrequency::Mhz16 => true,
         PulseRepetitionFrequency::Mhz64 => false,
@@ -90,6 +110,29 @@ pub fn get_range_bias_cm(rsl: f32, rx_config: &RxConfig) -> f32 {
     }
 }
 
+/// Estimates the RSL in dBm from the quality of a received message
+///
+/// This is the value [`get_range_bias_cm`] expects.
+pub fn estimate_rsl(rx_quality: &RxQuality, rx_config: &RxConfig) -> f32 {
+    improve_rssi_estimation(rx_quality.rssi, rx_config)
+}
+
+/// Corrects a measured distance for the range bias
+///
+/// `rx_quality` is the quality of the message the distance was computed from,
+/// and `rx_config` the config it was received with. The result saturates at
+/// zero.
+pub fn correct_distance_mm(distance_mm: u64, rx_quality: &RxQuality, rx_config: &RxConfig) -> u64 {
+    let bias_mm = get_range_bias_cm(estimate_rsl(rx_quality, rx_config), rx_config) * 10.0;
+    let distance_mm = distance_mm as f32 - bias_mm;
+
+    if distance_mm > 0.0 {
+        distance_mm as u64
+    } else {
+        0
+    }
+}
+
 /// Tries to improve the rssi estimation with figure 22 from the user manual (2.18)
 pub fn improve_rssi_estimation(original_rssi: f32, rx_config: &crate::configs::RxConfig) -> f32 {
     #[allow(unused_imports)]
@@ -208,6 +251,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn range_bias_cm_wide_band_channels() {
+        for channel in [UwbChannel::Channel4, UwbChannel::Channel7] {
+            let rx_config = RxConfig {
+                channel,
+                pulse_repetition_frequency: PulseRepetitionFrequency::Mhz64,
+                ..RxConfig::default()
+            };
+
+            assert_eq!(
+                get_range_bias_cm(-94.0, &rx_config),
+                (RANGE_BIAS_CORRECTION_PRF64_MHZ900[0] + RANGE_BIAS_CORRECTION_PRF64_MHZ900[1])
+                    / 2.0
+            );
+        }
+    }
+
+    #[test]
+    fn correct_distance() {
+        let rx_config = RxConfig::default();
+
+        // At -93 dBm, ranges are measured 11 cm too long
+        let weak = RxQuality {
+            los_confidence_level: 1.0,
+            rssi: -93.0,
+        };
+        assert_eq!(estimate_rsl(&weak, &rx_config), -93.0);
+        assert_eq!(correct_distance_mm(5000, &weak, &rx_config), 4890);
+        assert_eq!(correct_distance_mm(50, &weak, &rx_config), 0);
+
+        // Strong signals are underestimated, and ranges measured too short
+        let strong = RxQuality {
+            los_confidence_level: 1.0,
+            rssi: -80.0,
+        };
+        assert!(estimate_rsl(&strong, &rx_config) > -80.0);
+        assert!(correct_distance_mm(5000, &strong, &rx_config) > 5150);
+    }
+
     #[test]
     fn improve_rssi_rough_correctness() {
         let rx_config = RxConfig::default();
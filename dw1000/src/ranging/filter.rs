@@ -0,0 +1,370 @@
+//! Filtering of range measurements
+//!
+//! Range measurements are noisy, and can jump considerably, if there's no line
+//! of sight between the two nodes. [`RangeFilter`] smoothes the measurements
+//! to each peer using a constant-velocity Kalman filter, and rejects
+//! measurements that are too far off from the predicted distance.
+
+use heapless::LinearMap;
+
+use super::{Message, RxMessage};
+use crate::{hl::RxQuality, mac};
+
+/// Configuration of a [`RangeFilter`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterConfig {
+    /// The number of ticks per second
+    ///
+    /// This defines the unit of the `now` argument of [`RangeFilter::update`].
+    pub ticks_per_second: u32,
+
+    /// The variance of a range measurement in mm²
+    pub measurement_variance: f32,
+
+    /// The process noise, as the spectral density of the acceleration in
+    /// mm²/s³
+    ///
+    /// Larger values allow the filter to follow quick changes in distance,
+    /// smaller values result in a smoother output.
+    pub process_noise: f32,
+
+    /// The variance of the relative velocity of a new peer in (mm/s)²
+    pub initial_velocity_variance: f32,
+
+    /// Measurements are rejected, if their distance from the predicted value
+    /// is larger than this many standard deviations
+    pub gate: f32,
+
+    /// The filter of a peer is restarted after this many consecutive rejected
+    /// measurements
+    ///
+    /// This allows the filter to recover, if the distance changed in a way
+    /// the filter couldn't follow.
+    pub max_rejections: u32,
+
+    /// Inflates the measurement variance, if there likely was no line of sight
+    ///
+    /// If this is set, the measurement variance is multiplied by
+    /// `1 + los_inflation * (1 - los_confidence_level)`, for measurements
+    /// that are passed with an [`RxQuality`].
+    pub los_inflation: Option<f32>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            ticks_per_second: 1000,
+            measurement_variance: 100.0 * 100.0,
+            process_noise: 1000.0 * 1000.0,
+            initial_velocity_variance: 1000.0 * 1000.0,
+            gate: 3.0,
+            max_rejections: 5,
+            los_inflation: None,
+        }
+    }
+}
+
+/// A filtered range, as returned by [`RangeFilter`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilteredRange {
+    /// The filtered distance in mm
+    pub distance_mm: f32,
+
+    /// The variance of the filtered distance in mm²
+    pub variance: f32,
+
+    /// The rate of change of the distance in mm/s
+    pub velocity: f32,
+
+    /// Indicates whether the last measurement was rejected
+    ///
+    /// If this is `true`, the other values are the prediction of the filter.
+    pub rejected: bool,
+}
+
+/// Filters range measurements to up to `N` peers
+///
+/// If a measurement to a new peer arrives while the table is full, the peer
+/// that was updated least recently is dropped.
+#[derive(Debug)]
+pub struct RangeFilter<const N: usize> {
+    config: FilterConfig,
+    peers: LinearMap<mac::Address, PeerFilter, N>,
+}
+
+impl<const N: usize> RangeFilter<N> {
+    /// Creates a new range filter
+    pub fn new(config: FilterConfig) -> Self {
+        RangeFilter {
+            config,
+            peers: LinearMap::new(),
+        }
+    }
+
+    /// Returns the configuration of the filter
+    pub fn config(&self) -> &FilterConfig {
+        &self.config
+    }
+
+    /// Adds a range measurement to a peer
+    ///
+    /// `now` is the current value of the monotonic tick, as configured by
+    /// [`FilterConfig::ticks_per_second`]. `rx_quality`, if available, is the
+    /// quality of the message the distance was computed from.
+    pub fn update(
+        &mut self,
+        peer: mac::Address,
+        distance_mm: u64,
+        rx_quality: Option<&RxQuality>,
+        now: u64,
+    ) -> FilteredRange {
+        let config = &self.config;
+
+        let mut variance = config.measurement_variance;
+        if let (Some(inflation), Some(rx_quality)) = (config.los_inflation, rx_quality) {
+            variance *= 1.0 + inflation * (1.0 - rx_quality.los_confidence_level);
+        }
+
+        let distance = distance_mm as f32;
+
+        if let Some(filter) = self.peers.get_mut(&peer) {
+            return filter.update(config, distance, variance, now);
+        }
+
+        if self.peers.len() == N {
+            let stalest = self
+                .peers
+                .iter()
+                .max_by_key(|(_, filter)| now.wrapping_sub(filter.last_update))
+                .map(|(&address, _)| address);
+            if let Some(stalest) = stalest {
+                self.peers.remove(&stalest);
+            }
+        }
+
+        let filter = PeerFilter::new(config, distance, variance, now);
+        let range = filter.range(false);
+
+        // If `N` is 0, there's no room for the filter. The measurement is
+        // still returned.
+        let _ = self.peers.insert(peer, filter);
+
+        range
+    }
+
+    /// Adds a range measurement that was computed from a message
+    ///
+    /// The peer is the source of the message. Returns `None`, if the message
+    /// has no source address.
+    pub fn update_from_message<T: Message>(
+        &mut self,
+        message: &RxMessage<T>,
+        distance_mm: u64,
+        rx_quality: Option<&RxQuality>,
+        now: u64,
+    ) -> Option<FilteredRange> {
+        let peer = message.source?;
+        Some(self.update(peer, distance_mm, rx_quality, now))
+    }
+
+    /// Returns the current estimate for a peer
+    ///
+    /// This is the estimate as of the last update. Returns `None`, if there's
+    /// no filter for the peer.
+    pub fn get(&self, peer: &mac::Address) -> Option<FilteredRange> {
+        self.peers.get(peer).map(|filter| filter.range(false))
+    }
+
+    /// Removes the filter for a peer
+    pub fn remove(&mut self, peer: &mac::Address) {
+        self.peers.remove(peer);
+    }
+
+    /// Removes the filters of all peers
+    pub fn clear(&mut self) {
+        self.peers.clear();
+    }
+}
+
+/// The Kalman filter for a single peer
+///
+/// The state consists of the distance and its rate of change.
+#[derive(Clone, Copy, Debug)]
+struct PeerFilter {
+    distance: f32,
+    velocity: f32,
+    covariance: [[f32; 2]; 2],
+    last_update: u64,
+    rejections: u32,
+}
+
+impl PeerFilter {
+    fn new(config: &FilterConfig, distance: f32, variance: f32, now: u64) -> Self {
+        PeerFilter {
+            distance,
+            velocity: 0.0,
+            covariance: [[variance, 0.0], [0.0, config.initial_velocity_variance]],
+            last_update: now,
+            rejections: 0,
+        }
+    }
+
+    fn update(
+        &mut self,
+        config: &FilterConfig,
+        distance: f32,
+        variance: f32,
+        now: u64,
+    ) -> FilteredRange {
+        let dt = now.wrapping_sub(self.last_update) as f32 / config.ticks_per_second as f32;
+        self.last_update = now;
+
+        // Predict
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let q = config.process_noise;
+        self.distance += self.velocity * dt;
+        self.covariance = [
+            [
+                p00 + dt * (p10 + p01) + dt * dt * p11 + q * dt * dt * dt / 3.0,
+                p01 + dt * p11 + q * dt * dt / 2.0,
+            ],
+            [p10 + dt * p11 + q * dt * dt / 2.0, p11 + q * dt],
+        ];
+
+        // Gate
+        let innovation = distance - self.distance;
+        let innovation_variance = self.covariance[0][0] + variance;
+        if innovation * innovation > config.gate * config.gate * innovation_variance {
+            self.rejections += 1;
+            if self.rejections > config.max_rejections {
+                *self = PeerFilter::new(config, distance, variance, now);
+                return self.range(false);
+            }
+            return self.range(true);
+        }
+        self.rejections = 0;
+
+        // Correct
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let k0 = p00 / innovation_variance;
+        let k1 = p10 / innovation_variance;
+        self.distance += k0 * innovation;
+        self.velocity += k1 * innovation;
+        self.covariance = [
+            [p00 - k0 * p00, p01 - k0 * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+
+        self.range(false)
+    }
+
+    fn range(&self, rejected: bool) -> FilteredRange {
+        FilteredRange {
+            distance_mm: self.distance,
+            variance: self.covariance[0][0],
+            velocity: self.velocity,
+            rejected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(id: u16) -> mac::Address {
+        mac::Address::Short(mac::PanId(0x0d57), mac::ShortAddress(id))
+    }
+
+    #[test]
+    fn smoothes_noise() {
+        let mut filter = RangeFilter::<4>::new(FilterConfig::default());
+
+        let mut range = filter.update(peer(1), 5000, None, 0);
+        assert_eq!(range.distance_mm, 5000.0);
+
+        let noise = [80, -120, 50, -30, 110, -90, 20, -60, 100, -40];
+        for (i, noise) in noise.iter().cycle().take(50).enumerate() {
+            let distance = (5000 + noise) as u64;
+            range = filter.update(peer(1), distance, None, 100 * (i as u64 + 1));
+            assert!(!range.rejected);
+        }
+
+        assert!((range.distance_mm - 5000.0).abs() < 50.0, "{:?}", range);
+        assert!(range.variance < FilterConfig::default().measurement_variance);
+    }
+
+    #[test]
+    fn follows_motion() {
+        let mut filter = RangeFilter::<4>::new(FilterConfig::default());
+
+        // Moving away at 500 mm/s
+        let mut range = filter.update(peer(1), 2000, None, 0);
+        for i in 1..=40 {
+            range = filter.update(peer(1), 2000 + 50 * i, None, 100 * i);
+        }
+
+        assert!((range.distance_mm - 4000.0).abs() < 10.0, "{:?}", range);
+        assert!((range.velocity - 500.0).abs() < 10.0, "{:?}", range);
+    }
+
+    #[test]
+    fn rejects_outliers() {
+        let config = FilterConfig::default();
+        let mut filter = RangeFilter::<4>::new(config);
+
+        for i in 0..20 {
+            filter.update(peer(1), 3000, None, 100 * i);
+        }
+
+        // A single non-line-of-sight measurement is rejected
+        let range = filter.update(peer(1), 4500, None, 2000);
+        assert!(range.rejected);
+        assert!((range.distance_mm - 3000.0).abs() < 1.0);
+
+        // If the jump persists, the filter is restarted
+        let mut range = range;
+        for i in 0..config.max_rejections as u64 {
+            range = filter.update(peer(1), 4500, None, 2100 + 100 * i);
+        }
+        assert!(!range.rejected);
+        assert_eq!(range.distance_mm, 4500.0);
+    }
+
+    #[test]
+    fn inflates_variance_without_line_of_sight() {
+        let config = FilterConfig {
+            los_inflation: Some(9.0),
+            ..FilterConfig::default()
+        };
+        let mut filter = RangeFilter::<4>::new(config);
+
+        let los = RxQuality {
+            los_confidence_level: 1.0,
+            rssi: -80.0,
+        };
+        let nlos = RxQuality {
+            los_confidence_level: 0.0,
+            rssi: -80.0,
+        };
+
+        let range = filter.update(peer(1), 1000, Some(&los), 0);
+        assert_eq!(range.variance, config.measurement_variance);
+        let range = filter.update(peer(2), 1000, Some(&nlos), 0);
+        assert_eq!(range.variance, 10.0 * config.measurement_variance);
+    }
+
+    #[test]
+    fn drops_stalest_peer() {
+        let mut filter = RangeFilter::<2>::new(FilterConfig::default());
+
+        filter.update(peer(1), 1000, None, 0);
+        filter.update(peer(2), 2000, None, 10);
+        filter.update(peer(1), 1000, None, 20);
+        filter.update(peer(3), 3000, None, 30);
+
+        assert!(filter.get(&peer(1)).is_some());
+        assert!(filter.get(&peer(2)).is_none());
+        assert!(filter.get(&peer(3)).is_some());
+    }
+}
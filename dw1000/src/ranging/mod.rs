@@ -0,0 +1,1097 @@
+//! Implementation of two-way ranging
+//!
+//! This ranging technique is described in the DW1000 user manual, section 12.3.
+//! This module uses three messages for a double-sided range measurement, as
+//! described in section 12.3.2. A single-sided variant that only needs two
+//! messages is also available (see below).
+//!
+//! This module defines the messages required, and provides code for sending and
+//! decoding them. It is left to the user to tie all that together, by sending
+//! out the messages at the right time.
+//!
+//! There can be some variation in the use of this module, depending on the use
+//! case. Here is one example of how this module can be used:
+//! 1. Nodes are divided into anchors and tags. Tags are those nodes whose
+//!    position interests us. Anchors are placed in known locations to enable
+//!    range measurements.
+//! 2. Anchors regularly send out pings ([`Ping`]).
+//! 3. Tags listen for these pings, and reply with a ranging request
+//!    ([`Request`]) for each ping they receive.
+//! 4. When an anchor receives a ranging request, it replies with a ranging
+//!    response ([`Response`]).
+//! 5. Once the tag receives the ranging response, it has all the information it
+//!    needs to compute the distance.
+//!
+//! Please refer to the [examples] in the DWM1001 Board Support Crate for an
+//! implementation of this scheme. Alternatively, [`RangingInitiator`] (for
+//! anchors) and [`RangingResponder`] (for tags) implement it, taking care of
+//! sending, receiving and timeouts.
+//!
+//! In this scheme, anchors initiate the exchange, which results in the tag
+//! having the distance information. Possible variations include the tag
+//! initiating the request and the anchor calculating the distance, or a
+//! peer-to-peer scheme without dedicated tags and anchors.
+//!
+//! # Single-sided ranging
+//!
+//! Single-sided two-way ranging only needs two messages, which halves the
+//! airtime compared to the scheme above:
+//! 1. The initiator sends a poll ([`SsTwrPoll`]).
+//! 2. The responder replies with a response ([`SsTwrResponse`]), which
+//!    contains the time stamps of receiving the poll and sending the response.
+//! 3. Once the initiator receives the response, it reads the clock offset of
+//!    the responder (see [`DW1000::read_clock_offset_ppm`]) and passes it to
+//!    [`compute_distance_ss_twr`].
+//!
+//! As the reply time is measured by the responder's clock, any clock offset
+//! between the nodes directly affects the result. Without compensating for it,
+//! the result is unusable.
+//!
+//! Any clock offset that remains after the compensation causes an error of
+//! the offset times the reply time. With a reply time of 10 ms, 1 ppm
+//! amounts to 10 ns, which is about 3 metres. Keep the reply time short,
+//! using the default of [`SS_TWR_REPLY_DELAY_NS`] or less, if the responder
+//! can prepare the response in time.
+//!
+//! # Asymmetric double-sided ranging
+//!
+//! In the tag-initiated double-sided scheme, the initiator starts the
+//! exchange, and the reply times don't need to be equal:
+//! 1. The initiator sends a poll ([`DsTwrPoll`]).
+//! 2. The responder replies with a response ([`DsTwrResponse`]).
+//! 3. The initiator replies with a final message ([`DsTwrFinal`]). The
+//!    responder can now compute the result using [`compute_ds_twr`].
+//! 4. Optionally, the responder sends a report ([`DsTwrReport`]) back, so the
+//!    initiator can compute the result using [`compute_ds_twr_from_report`].
+//!
+//! Please note that using the code in this module without further processing of
+//! the result will yield imprecise measurements. To improve the precision of
+//! those measurements, a range bias needs to be applied, for example using
+//! [`compute_corrected_distance_mm`] or [`range_bias::correct_distance_mm`].
+//! Please refer to the user manual, and [this DWM1001 issue] for more
+//! information.
+//!
+//! Measured distances are also noisy. [`filter::RangeFilter`] can be used to
+//! smooth them, and to reject outliers.
+//!
+//! [`Ping`]: struct.Ping.html
+//! [`Request`]: struct.Request.html
+//! [`Response`]: struct.Response.html
+//! [`DW1000::read_clock_offset_ppm`]: ../hl/struct.DW1000.html#method.read_clock_offset_ppm
+//! [examples]: https://github.com/braun-robotics/rust-dwm1001/tree/master/examples
+//! [this DWM1001 issue]: https://github.com/braun-robotics/rust-dwm1001/issues/55
+
+pub mod filter;
+pub mod tdoa;
+
+mod session;
+
+pub use self::session::*;
+
+use core::mem::size_of;
+
+use embedded_hal::spi::SpiDevice;
+use serde::{Deserialize, Serialize};
+use ssmarshal;
+
+use crate::hl::SendTime;
+use crate::{
+    hl, mac, range_bias,
+    time::{Duration, Instant},
+    Error, Ready, RxConfig, Sending, TxConfig, DW1000,
+};
+
+/// The transmission delay
+///
+/// This defines the transmission delay as 10 ms. This should be enough to
+/// finish the rest of the preparation and send the message, even if we're
+/// running with unoptimized code.
+const TX_DELAY: u32 = 10_000_000;
+
+/// The default reply delay of single-sided ranging, in nanoseconds
+///
+/// The error of a single-sided range measurement scales with the reply time
+/// (see [module documentation]), so this is much shorter than the delay of
+/// the other messages. At 500 µs, an uncompensated clock offset of 1 ppm
+/// causes an error of 0.5 ns, or about 15 cm.
+///
+/// [module documentation]: index.html
+pub const SS_TWR_REPLY_DELAY_NS: u32 = 500_000;
+
+/// The default transmission delay of the double-sided poll, in nanoseconds
+///
+/// Double-sided ranging compensates for the clock offset between the nodes,
+/// so unlike with [`SS_TWR_REPLY_DELAY_NS`], a longer delay doesn't hurt the
+/// accuracy. This is the same 10 ms that the other messages use.
+pub const DS_TWR_POLL_DELAY_NS: u32 = TX_DELAY;
+
+/// Implemented by all ranging messages
+pub trait Message: Sized + for<'de> Deserialize<'de> + Serialize {
+    /// A prelude that identifies the message
+    const PRELUDE: Prelude;
+
+    /// The length of the message's prelude
+    ///
+    /// This is a bit of a hack that we need until `slice::<impl [T]>::len` is
+    /// stable as a const fn.
+    const PRELUDE_LEN: usize;
+
+    /// The length of the whole message, including prelude and data
+    const LEN: usize = Self::PRELUDE_LEN + size_of::<Self>();
+
+    /// Decodes a received message of this type
+    ///
+    /// The user is responsible for receiving a message using
+    /// [`DW1000::receive`]. Once a message has been received, this method can
+    /// be used to check what type of message this is.
+    ///
+    /// Returns `Ok(None)`, if the message is not of the right type. Otherwise,
+    /// returns `Ok(Some(RxMessage<Self>)), if the message is of the right type,
+    /// and no error occured.
+    fn decode<SPI>(message: &hl::Message) -> Result<Option<RxMessage<Self>>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        if !message.frame.payload.starts_with(Self::PRELUDE.0) {
+            // Not a message of this type
+            return Ok(None);
+        }
+
+        if message.frame.payload.len() != Self::LEN {
+            // Invalid message
+            return Err(Error::BufferTooSmall {
+                required_len: Self::LEN,
+            });
+        }
+
+        // The message passes muster. Let's decode it.
+        let (payload, _) =
+            ssmarshal::deserialize::<Self>(&message.frame.payload[Self::PRELUDE.0.len()..])?;
+
+        Ok(Some(RxMessage {
+            rx_time: message.rx_time,
+            source: message.frame.header.source,
+            payload,
+        }))
+    }
+}
+
+/// An incoming ranging message
+///
+/// Contains the received payload, as well as some metadata that's required to
+/// create a reply to the message.
+#[derive(Debug)]
+pub struct RxMessage<T: Message> {
+    /// The time the message was received
+    pub rx_time: Instant,
+
+    /// The source of the message
+    pub source: Option<mac::Address>,
+
+    /// The message data
+    pub payload: T,
+}
+
+/// An outgoing ranging message
+///
+/// Contains the payload to be sent, as well as some metadata.
+#[derive(Debug)]
+pub struct TxMessage<T: Message> {
+    /// The recipient of the message
+    ///
+    /// This is an IEEE 802.15.4 MAC address. This could be a broadcast address,
+    /// for messages that are sent to all other nodes in range.
+    pub recipient: Option<mac::Address>,
+
+    /// The time this message is going to be sent
+    ///
+    /// When creating this struct, this is going to be an instant in the near
+    /// future. When sending the message, the sending is delayed to make sure it
+    /// it sent at exactly this instant.
+    pub tx_time: Instant,
+
+    /// The actual message payload
+    pub payload: T,
+}
+
+impl<T> TxMessage<T>
+where
+    T: Message,
+{
+    /// Send this message via the DW1000
+    ///
+    /// Serializes the message payload and uses [`DW1000::send`] internally to
+    /// send it.
+    pub fn send<SPI>(&self, dw1000: DW1000<SPI, Ready>) -> Result<DW1000<SPI, Sending>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        self.try_send(dw1000, TxConfig::default())
+            .map_err(|(_, error)| error)
+    }
+
+    /// Like `send`, but with the given config, and returns the DW1000 along
+    /// with the error
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn try_send<SPI>(
+        &self,
+        dw1000: DW1000<SPI, Ready>,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, Sending>, (DW1000<SPI, Ready>, Error<SPI>)>
+    where
+        SPI: SpiDevice,
+    {
+        // Create a buffer that fits the biggest message currently implemented.
+        // This is a really ugly hack. The size of the buffer should just be
+        // `T::LEN`. Unfortunately that's not possible. See:
+        // https://github.com/rust-lang/rust/issues/42863
+        const LEN: usize = 56;
+        assert!(T::LEN <= LEN);
+        let mut buf = [0; LEN];
+
+        buf[..T::PRELUDE.0.len()].copy_from_slice(T::PRELUDE.0);
+        if let Err(error) = ssmarshal::serialize(&mut buf[T::PRELUDE.0.len()..], &self.payload) {
+            return Err((dw1000, error.into()));
+        }
+
+        dw1000.try_send(
+            &buf[..T::LEN],
+            self.recipient,
+            SendTime::Delayed(self.tx_time),
+            config,
+        )
+    }
+}
+
+/// Sent before a message's data to identify the message
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct Prelude(pub &'static [u8]);
+
+/// Ranging ping message
+///
+/// This message is typically sent to initiate a range measurement transaction.
+/// See [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct Ping {
+    /// When the ping was sent, in local sender time
+    pub ping_tx_time: Instant,
+}
+
+impl Ping {
+    /// Creates a new ping message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to 10 milliseconds in the future. Make sure to send the message
+    /// within that time frame, or the distance measurement will be negatively
+    /// affected.
+    pub fn new<SPI>(dw1000: &mut DW1000<SPI, Ready>) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(TX_DELAY);
+        let ping_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let payload = Ping { ping_tx_time };
+
+        Ok(TxMessage {
+            recipient: mac::Address::broadcast(&mac::AddressMode::Short),
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for Ping {
+    const PRELUDE: Prelude = Prelude(b"RANGING PING");
+    const PRELUDE_LEN: usize = 12;
+}
+
+/// Ranging request message
+///
+/// This message is typically sent in response to a ranging ping, to request a
+/// ranging response. See [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct Request {
+    /// When the original ping was sent, in local time on the anchor
+    pub ping_tx_time: Instant,
+
+    /// The time between the ping being received and the reply being sent
+    pub ping_reply_time: Duration,
+
+    /// When the ranging request was sent, in local sender time
+    pub request_tx_time: Instant,
+}
+
+impl Request {
+    /// Creates a new ranging request message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to 10 milliseconds in the future. Make sure to send the message
+    /// within that time frame, or the distance measurement will be negatively
+    /// affected.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        ping: &RxMessage<Ping>,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(TX_DELAY);
+        let request_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let ping_reply_time = request_tx_time.duration_since(ping.rx_time);
+
+        let payload = Request {
+            ping_tx_time: ping.payload.ping_tx_time,
+            ping_reply_time,
+            request_tx_time,
+        };
+
+        Ok(TxMessage {
+            recipient: ping.source,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for Request {
+    const PRELUDE: Prelude = Prelude(b"RANGING REQUEST");
+    const PRELUDE_LEN: usize = 15;
+}
+
+/// Ranging response message
+///
+/// This message is typically sent in response to a ranging request, to wrap up
+/// the range measurement transaction.. See [module documentation] for more
+/// info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct Response {
+    /// The time between the ping being received and the reply being sent
+    pub ping_reply_time: Duration,
+
+    /// The time between the ping being sent and the reply being received
+    pub ping_round_trip_time: Duration,
+
+    /// The time the ranging request was sent, in local sender time
+    pub request_tx_time: Instant,
+
+    /// The time between the request being received and a reply being sent
+    pub request_reply_time: Duration,
+}
+
+impl Response {
+    /// Creates a new ranging response message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to 10 milliseconds in the future. Make sure to send the message
+    /// within that time frame, or the distance measurement will be negatively
+    /// affected.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        request: &RxMessage<Request>,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(TX_DELAY);
+        let response_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let ping_round_trip_time = request.rx_time.duration_since(request.payload.ping_tx_time);
+        let request_reply_time = response_tx_time.duration_since(request.rx_time);
+
+        let payload = Response {
+            ping_reply_time: request.payload.ping_reply_time,
+            ping_round_trip_time,
+            request_tx_time: request.payload.request_tx_time,
+            request_reply_time,
+        };
+
+        Ok(TxMessage {
+            recipient: request.source,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for Response {
+    const PRELUDE: Prelude = Prelude(b"RANGING RESPONSE");
+    const PRELUDE_LEN: usize = 16;
+}
+
+/// Double-sided ranging poll message
+///
+/// This message is sent by the initiator to start an asymmetric double-sided
+/// range measurement. See [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct DsTwrPoll {
+    /// When the poll was sent, in local sender time
+    pub poll_tx_time: Instant,
+}
+
+impl DsTwrPoll {
+    /// Creates a new poll message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to `delay_ns` nanoseconds in the future. Make sure to send the
+    /// message within that time frame, or the distance measurement will be
+    /// negatively affected. [`DS_TWR_POLL_DELAY_NS`] is a reasonable default.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        recipient: Option<mac::Address>,
+        delay_ns: u32,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(delay_ns);
+        let poll_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let payload = DsTwrPoll { poll_tx_time };
+
+        Ok(TxMessage {
+            recipient,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for DsTwrPoll {
+    const PRELUDE: Prelude = Prelude(b"RANGING DS-TWR POLL");
+    const PRELUDE_LEN: usize = 19;
+}
+
+/// Double-sided ranging response message
+///
+/// This message is sent by the responder in response to a poll. See
+/// [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct DsTwrResponse {
+    /// When the poll was sent, in local time on the initiator
+    pub poll_tx_time: Instant,
+
+    /// The time between the poll being received and the response being sent
+    pub poll_reply_time: Duration,
+
+    /// When the response was sent, in local sender time
+    pub response_tx_time: Instant,
+}
+
+impl DsTwrResponse {
+    /// Creates a new response message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to 10 milliseconds in the future. Make sure to send the message
+    /// within that time frame, or the distance measurement will be negatively
+    /// affected.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        poll: &RxMessage<DsTwrPoll>,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(TX_DELAY);
+        let response_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let payload = DsTwrResponse {
+            poll_tx_time: poll.payload.poll_tx_time,
+            poll_reply_time: response_tx_time.duration_since(poll.rx_time),
+            response_tx_time,
+        };
+
+        Ok(TxMessage {
+            recipient: poll.source,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for DsTwrResponse {
+    const PRELUDE: Prelude = Prelude(b"RANGING DS-TWR RESPONSE");
+    const PRELUDE_LEN: usize = 23;
+}
+
+/// Double-sided ranging final message
+///
+/// This message is sent by the initiator in response to a response, and
+/// contains everything the responder needs to compute the distance. See
+/// [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct DsTwrFinal {
+    /// The time between the poll being sent and the response being received
+    pub poll_round_trip_time: Duration,
+
+    /// The time between the poll being received and the response being sent
+    pub poll_reply_time: Duration,
+
+    /// When the response was sent, in local time on the responder
+    pub response_tx_time: Instant,
+
+    /// The time between the response being received and the final message
+    /// being sent
+    pub response_reply_time: Duration,
+}
+
+impl DsTwrFinal {
+    /// Creates a new final message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to 10 milliseconds in the future. Make sure to send the message
+    /// within that time frame, or the distance measurement will be negatively
+    /// affected.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        response: &RxMessage<DsTwrResponse>,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(TX_DELAY);
+        let final_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let payload = DsTwrFinal {
+            poll_round_trip_time: response
+                .rx_time
+                .duration_since(response.payload.poll_tx_time),
+            poll_reply_time: response.payload.poll_reply_time,
+            response_tx_time: response.payload.response_tx_time,
+            response_reply_time: final_tx_time.duration_since(response.rx_time),
+        };
+
+        Ok(TxMessage {
+            recipient: response.source,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for DsTwrFinal {
+    const PRELUDE: Prelude = Prelude(b"RANGING DS-TWR FINAL");
+    const PRELUDE_LEN: usize = 20;
+}
+
+/// Double-sided ranging report message
+///
+/// This message is optionally sent by the responder after receiving the final
+/// message, so the initiator can compute the distance too. See
+/// [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct DsTwrReport {
+    /// The time between the poll being sent and the response being received
+    pub poll_round_trip_time: Duration,
+
+    /// The time between the poll being received and the response being sent
+    pub poll_reply_time: Duration,
+
+    /// The time between the response being sent and the final message being
+    /// received
+    pub response_round_trip_time: Duration,
+
+    /// The time between the response being received and the final message
+    /// being sent
+    pub response_reply_time: Duration,
+}
+
+impl DsTwrReport {
+    /// Creates a new report message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to 10 milliseconds in the future. The timing of this message
+    /// doesn't affect the distance measurement.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        final_message: &RxMessage<DsTwrFinal>,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(TX_DELAY);
+
+        let payload = DsTwrReport {
+            poll_round_trip_time: final_message.payload.poll_round_trip_time,
+            poll_reply_time: final_message.payload.poll_reply_time,
+            response_round_trip_time: final_message
+                .rx_time
+                .duration_since(final_message.payload.response_tx_time),
+            response_reply_time: final_message.payload.response_reply_time,
+        };
+
+        Ok(TxMessage {
+            recipient: final_message.source,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for DsTwrReport {
+    const PRELUDE: Prelude = Prelude(b"RANGING DS-TWR REPORT");
+    const PRELUDE_LEN: usize = 21;
+}
+
+/// Single-sided ranging poll message
+///
+/// This message is sent to initiate a single-sided range measurement. See
+/// [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct SsTwrPoll {
+    /// When the poll was sent, in local sender time
+    pub poll_tx_time: Instant,
+}
+
+impl SsTwrPoll {
+    /// Creates a new poll message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to `delay_ns` nanoseconds in the future. Make sure to send the
+    /// message within that time frame, or the distance measurement will be
+    /// negatively affected. [`SS_TWR_REPLY_DELAY_NS`] is a reasonable default.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        recipient: Option<mac::Address>,
+        delay_ns: u32,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(delay_ns);
+        let poll_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let payload = SsTwrPoll { poll_tx_time };
+
+        Ok(TxMessage {
+            recipient,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for SsTwrPoll {
+    const PRELUDE: Prelude = Prelude(b"RANGING SS-TWR POLL");
+    const PRELUDE_LEN: usize = 19;
+}
+
+/// Single-sided ranging response message
+///
+/// This message is sent in response to a poll, to wrap up the single-sided
+/// range measurement. See [module documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct SsTwrResponse {
+    /// When the poll was sent, in local time on the initiator
+    pub poll_tx_time: Instant,
+
+    /// When the poll was received, in local sender time
+    pub poll_rx_time: Instant,
+
+    /// When the response was sent, in local sender time
+    pub response_tx_time: Instant,
+}
+
+impl SsTwrResponse {
+    /// Creates a new response message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to `reply_delay_ns` nanoseconds in the future. Make sure to send
+    /// the message within that time frame, or the distance measurement will be
+    /// negatively affected.
+    ///
+    /// The delay is part of the reply time, and any clock offset that isn't
+    /// compensated causes an error of the offset times the reply time. Use
+    /// [`SS_TWR_REPLY_DELAY_NS`], or the shortest delay the responder can
+    /// reliably meet.
+    pub fn new<SPI>(
+        dw1000: &mut DW1000<SPI, Ready>,
+        poll: &RxMessage<SsTwrPoll>,
+        reply_delay_ns: u32,
+    ) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(reply_delay_ns);
+        let response_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        let payload = SsTwrResponse {
+            poll_tx_time: poll.payload.poll_tx_time,
+            poll_rx_time: poll.rx_time,
+            response_tx_time,
+        };
+
+        Ok(TxMessage {
+            recipient: poll.source,
+            tx_time,
+            payload,
+        })
+    }
+}
+
+impl Message for SsTwrResponse {
+    const PRELUDE: Prelude = Prelude(b"RANGING SS-TWR RESPONSE");
+    const PRELUDE_LEN: usize = 23;
+}
+
+/// Computes the distance to another node from a single-sided ranging response
+///
+/// `clock_offset_ppm` is the clock offset of the responder relative to the
+/// local clock, as returned by [`DW1000::read_clock_offset_ppm`] for the
+/// received response. It is used to convert the responder's reply time into
+/// local time.
+///
+/// [`DW1000::read_clock_offset_ppm`]: ../hl/struct.DW1000.html#method.read_clock_offset_ppm
+pub fn compute_distance_ss_twr(
+    response: &RxMessage<SsTwrResponse>,
+    clock_offset_ppm: f32,
+) -> Result<u64, ComputeDistanceError> {
+    let round_trip_time = response
+        .rx_time
+        .duration_since(response.payload.poll_tx_time)
+        .value();
+    let reply_time = response
+        .payload
+        .response_tx_time
+        .duration_since(response.payload.poll_rx_time)
+        .value();
+
+    // A positive offset means the responder's clock is running faster, so its
+    // reply time is longer than what the local clock would have measured.
+    let reply_time = reply_time as f64 * (1.0 - clock_offset_ppm as f64 * 1e-6);
+
+    let time_diff = round_trip_time as f64 - reply_time;
+    if time_diff < 0.0 {
+        return Err(ComputeDistanceError::RtGreaterThanRtt);
+    }
+
+    distance_mm_from_time_of_flight((time_diff / 2.0) as u64)
+}
+
+/// Computes the distance to another node from a ranging response
+pub fn compute_distance_mm(response: &RxMessage<Response>) -> Result<u64, ComputeDistanceError> {
+    // To keep variable names to a reasonable length, this function uses `rt` as
+    // a short-hand for "reply time" and `rtt` and a short-hand for "round-trip
+    // time".
+
+    let ping_rt = response.payload.ping_reply_time.value();
+    let ping_rtt = response.payload.ping_round_trip_time.value();
+    let request_rt = response.payload.request_reply_time.value();
+    let request_rtt = response
+        .rx_time
+        .duration_since(response.payload.request_tx_time)
+        .value();
+
+    let time_of_flight = time_of_flight(ping_rtt, ping_rt, request_rtt, request_rt)?;
+
+    distance_mm_from_time_of_flight(time_of_flight)
+}
+
+/// Computes the distance to another node from a ranging response, corrected
+/// for the range bias
+///
+/// `rx_quality` is the quality of the response, as returned by
+/// [`DW1000::read_rx_quality`], and `rx_config` the config it was received
+/// with. See [`range_bias`] for details.
+///
+/// [`DW1000::read_rx_quality`]: crate::DW1000::read_rx_quality
+/// [`range_bias`]: crate::range_bias
+pub fn compute_corrected_distance_mm(
+    response: &RxMessage<Response>,
+    rx_quality: &hl::RxQuality,
+    rx_config: &RxConfig,
+) -> Result<u64, ComputeDistanceError> {
+    let distance_mm = compute_distance_mm(response)?;
+    Ok(range_bias::correct_distance_mm(
+        distance_mm,
+        rx_quality,
+        rx_config,
+    ))
+}
+
+/// Computes the result of an asymmetric double-sided range measurement
+///
+/// This is used by the responder, once it has received the final message.
+pub fn compute_ds_twr(
+    final_message: &RxMessage<DsTwrFinal>,
+) -> Result<RangingResult, ComputeDistanceError> {
+    let response_round_trip_time = final_message
+        .rx_time
+        .duration_since(final_message.payload.response_tx_time);
+
+    RangingResult::from_durations(
+        final_message.payload.poll_round_trip_time,
+        final_message.payload.poll_reply_time,
+        response_round_trip_time,
+        final_message.payload.response_reply_time,
+    )
+}
+
+/// Computes the result of an asymmetric double-sided range measurement from a
+/// report
+///
+/// This is used by the initiator, once it has received the report.
+pub fn compute_ds_twr_from_report(
+    report: &RxMessage<DsTwrReport>,
+) -> Result<RangingResult, ComputeDistanceError> {
+    RangingResult::from_durations(
+        report.payload.poll_round_trip_time,
+        report.payload.poll_reply_time,
+        report.payload.response_round_trip_time,
+        report.payload.response_reply_time,
+    )
+}
+
+/// The result of a double-sided range measurement
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RangingResult {
+    /// The time of flight in picoseconds
+    pub time_of_flight_ps: u64,
+
+    /// The distance in millimeters
+    pub distance_mm: u64,
+}
+
+impl RangingResult {
+    fn from_durations(
+        poll_round_trip_time: Duration,
+        poll_reply_time: Duration,
+        response_round_trip_time: Duration,
+        response_reply_time: Duration,
+    ) -> Result<Self, ComputeDistanceError> {
+        let time_of_flight = time_of_flight(
+            poll_round_trip_time.value(),
+            poll_reply_time.value(),
+            response_round_trip_time.value(),
+            response_reply_time.value(),
+        )?;
+
+        // Nominally, each time unit is 1/64 ns
+        let time_of_flight_ps = time_of_flight
+            .checked_mul(1000)
+            .ok_or(ComputeDistanceError::TimeOfFlightTooLarge)?
+            / 64;
+
+        Ok(RangingResult {
+            time_of_flight_ps,
+            distance_mm: distance_mm_from_time_of_flight(time_of_flight)?,
+        })
+    }
+}
+
+/// Computes the time of flight from the two round-trip times and the two reply
+/// times of a double-sided range measurement
+///
+/// Each reply time is the part of the respective round-trip time that was
+/// spent by the other node. All values and the result are in DW1000 time units.
+fn time_of_flight(
+    round_trip_time_1: u64,
+    reply_time_1: u64,
+    round_trip_time_2: u64,
+    reply_time_2: u64,
+) -> Result<u64, ComputeDistanceError> {
+    // To keep variable names to a reasonable length, this function uses `rt` as
+    // a short-hand for "reply time" and `rtt` and a short-hand for "round-trip
+    // time".
+
+    // Compute time of flight according to the formula given in the DW1000 user
+    // manual, section 12.3.2. It doesn't require the reply times to be equal.
+    let rtt_product = round_trip_time_1
+        .checked_mul(round_trip_time_2)
+        .ok_or(ComputeDistanceError::RoundTripTimesTooLarge)?;
+    let rt_product = reply_time_1
+        .checked_mul(reply_time_2)
+        .ok_or(ComputeDistanceError::ReplyTimesTooLarge)?;
+    let rt_sum = reply_time_1
+        .checked_add(reply_time_2)
+        .ok_or(ComputeDistanceError::SumTooLarge)?;
+    let rtt_sum = round_trip_time_1
+        .checked_add(round_trip_time_2)
+        .ok_or(ComputeDistanceError::SumTooLarge)?;
+    let sum = rt_sum
+        .checked_add(rtt_sum)
+        .ok_or(ComputeDistanceError::SumTooLarge)?;
+
+    let time_diff = rtt_product
+        .checked_sub(rt_product)
+        .ok_or(ComputeDistanceError::RtGreaterThanRtt)?;
+
+    Ok(time_diff / sum)
+}
+
+/// Converts a time of flight in DW1000 time units into millimeters
+fn distance_mm_from_time_of_flight(time_of_flight: u64) -> Result<u64, ComputeDistanceError> {
+    // Nominally, all time units are based on a 64 Ghz clock, meaning each time
+    // unit is 1/64 ns.
+
+    const SPEED_OF_LIGHT: u64 = 299_792_458; // m/s or nm/ns
+
+    let distance_nm_times_64 = SPEED_OF_LIGHT
+        .checked_mul(time_of_flight)
+        .ok_or(ComputeDistanceError::TimeOfFlightTooLarge)?;
+    let distance_mm = distance_nm_times_64 / 64 / 1_000_000;
+
+    Ok(distance_mm)
+}
+
+/// Returned from [`compute_distance_mm`] and the other functions computing a
+/// distance in case of an error
+#[derive(Debug)]
+pub enum ComputeDistanceError {
+    /// Reply times are too large to be multiplied
+    ReplyTimesTooLarge,
+
+    /// Round-trip times are too large to be multiplied
+    RoundTripTimesTooLarge,
+
+    /// The sum computed as part of the algorithm is too large
+    SumTooLarge,
+
+    /// The time of flight is so large, the distance calculation would overflow
+    TimeOfFlightTooLarge,
+
+    /// Round trip product is greater than round trip time due to low power
+    // Not exactly sure what causes this but it's a potential problem and occurs when VCC is low
+    RtGreaterThanRtt,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::TIME_MAX;
+
+    fn ss_twr_response(
+        poll_tx_time: u64,
+        poll_rx_time: u64,
+        response_tx_time: u64,
+        rx_time: u64,
+    ) -> RxMessage<SsTwrResponse> {
+        RxMessage {
+            rx_time: Instant::new(rx_time).unwrap(),
+            source: None,
+            payload: SsTwrResponse {
+                poll_tx_time: Instant::new(poll_tx_time).unwrap(),
+                poll_rx_time: Instant::new(poll_rx_time).unwrap(),
+                response_tx_time: Instant::new(response_tx_time).unwrap(),
+            },
+        }
+    }
+
+    fn duration(value: u64) -> Duration {
+        Duration::new(value).unwrap()
+    }
+
+    #[test]
+    fn ds_twr_with_asymmetric_reply_times() {
+        // 10 m are about 2135 time units
+        let tof = 2135;
+        let poll_reply_time = 640_000_000;
+        let response_reply_time = 320_000_000;
+
+        let final_message = RxMessage {
+            rx_time: Instant::new(9000 + response_reply_time + tof).unwrap(),
+            source: None,
+            payload: DsTwrFinal {
+                poll_round_trip_time: duration(poll_reply_time + 2 * tof),
+                poll_reply_time: duration(poll_reply_time),
+                response_tx_time: Instant::new(9000 - tof).unwrap(),
+                response_reply_time: duration(response_reply_time),
+            },
+        };
+
+        let result = compute_ds_twr(&final_message).unwrap();
+        assert_eq!(result.time_of_flight_ps, 2135 * 1000 / 64);
+        assert_eq!(result.distance_mm, 10_000);
+    }
+
+    #[test]
+    fn ds_twr_report_matches_final() {
+        let tof = 2135;
+        let report = RxMessage {
+            rx_time: Instant::new(0).unwrap(),
+            source: None,
+            payload: DsTwrReport {
+                poll_round_trip_time: duration(640_000_000 + 2 * tof),
+                poll_reply_time: duration(640_000_000),
+                response_round_trip_time: duration(320_000_000 + 2 * tof),
+                response_reply_time: duration(320_000_000),
+            },
+        };
+
+        let result = compute_ds_twr_from_report(&report).unwrap();
+        assert_eq!(result.distance_mm, 10_000);
+    }
+
+    #[test]
+    fn ss_twr_without_clock_offset() {
+        // 10 m are about 2135 time units
+        let tof = 2135;
+        let reply_time = 640_000_000;
+        let response = ss_twr_response(1000, 5000, 5000 + reply_time, 1000 + reply_time + 2 * tof);
+
+        let distance_mm = compute_distance_ss_twr(&response, 0.0).unwrap();
+        assert_eq!(distance_mm, 10_000);
+    }
+
+    #[test]
+    fn ss_twr_with_clock_offset() {
+        // The responder's clock runs 10 ppm fast, so it measures a reply time
+        // that is 10 ppm longer than the actual one.
+        let tof = 2135;
+        let reply_time = 640_000_000;
+        let remote_reply_time = reply_time + reply_time / 100_000;
+        let response = ss_twr_response(
+            1000,
+            5000,
+            5000 + remote_reply_time,
+            1000 + reply_time + 2 * tof,
+        );
+
+        // Without compensation, the result is way off
+        assert!(compute_distance_ss_twr(&response, 0.0).is_err());
+
+        let distance_mm = compute_distance_ss_twr(&response, 10.0).unwrap();
+        assert!(distance_mm.abs_diff(10_000) < 10);
+    }
+
+    #[test]
+    fn ss_twr_across_timer_overflow() {
+        let tof = 2135;
+        let reply_time = 640_000_000;
+        let poll_tx_time = TIME_MAX - 1000;
+        let rx_time = (poll_tx_time + reply_time + 2 * tof) & TIME_MAX;
+        let response = ss_twr_response(poll_tx_time, 5000, 5000 + reply_time, rx_time);
+
+        let distance_mm = compute_distance_ss_twr(&response, 0.0).unwrap();
+        assert_eq!(distance_mm, 10_000);
+    }
+}
@@ -0,0 +1,499 @@
+//! Ranging sessions that drive the DW1000 through whole range measurements
+//!
+//! The sessions implement the scheme described in the [module documentation]:
+//! A [`RangingInitiator`] regularly sends out pings and answers the ranging
+//! requests it receives. A [`RangingResponder`] answers pings with ranging
+//! requests and computes the distance from the responses.
+//!
+//! Both sessions own the `DW1000` and are non-blocking. Call `pump` whenever
+//! the DW1000 signals an interrupt, and regularly enough that timeouts can be
+//! detected. TX and RX interrupts need to be enabled for this to work (see
+//! [`DW1000::enable_tx_interrupts`] and [`DW1000::enable_rx_interrupts`]).
+//!
+//! Timeouts are based on a monotonic tick that is passed to `pump`. The unit
+//! of the tick is up to the user, as long as the timeouts are given in the
+//! same unit.
+//!
+//! If `pump` returns an error, the session keeps the DW1000 in the `Ready`
+//! state. Calling `pump` again retries, and `release` returns the DW1000.
+//!
+//! [module documentation]: super
+
+use core::mem;
+
+use embedded_hal::spi::SpiDevice;
+
+use super::{
+    compute_distance_mm, ComputeDistanceError, Message as _, Ping, Request, Response, TxMessage,
+};
+use crate::{
+    hl::{self, RxQuality},
+    mac, Error, Ready, RxConfig, Sending, SingleBufferReceiving, TxConfig, DW1000,
+};
+
+/// An event emitted by a ranging session
+#[derive(Debug)]
+pub enum RangingEvent {
+    /// The distance to a peer has been measured
+    Distance {
+        /// The peer the distance has been measured to
+        peer: Option<mac::Address>,
+
+        /// The measured distance in millimeters
+        distance_mm: u64,
+
+        /// The quality of the ranging response the distance was computed from
+        ///
+        /// This is `None`, if the quality could not be determined for this
+        /// response (see [`Error::BadRssiCalculation`]).
+        rx_quality: Option<RxQuality>,
+    },
+
+    /// A ranging request from a peer has been answered
+    ///
+    /// The peer computes the distance from the response.
+    RequestAnswered {
+        /// The peer whose request has been answered
+        peer: Option<mac::Address>,
+    },
+
+    /// A range measurement has failed
+    Failed {
+        /// The peer the distance should have been measured to, if known
+        peer: Option<mac::Address>,
+
+        /// The reason the range measurement has failed
+        reason: RangingFailure,
+    },
+}
+
+/// The reason a range measurement has failed
+#[derive(Debug)]
+pub enum RangingFailure {
+    /// The peer didn't respond in time
+    Timeout,
+
+    /// A ranging message has been received, but could not be decoded
+    Decode,
+
+    /// A ranging message could not be sent
+    Transmit,
+
+    /// The distance could not be computed
+    Distance(ComputeDistanceError),
+}
+
+/// Initiates range measurements by sending out pings
+///
+/// See [module documentation] for more info.
+///
+/// [module documentation]: index.html
+pub struct RangingInitiator<SPI> {
+    link: Link<SPI>,
+    ping_interval: u64,
+    last_ping: Option<u64>,
+    answering: Option<Option<mac::Address>>,
+}
+
+impl<SPI> RangingInitiator<SPI>
+where
+    SPI: SpiDevice,
+{
+    /// Creates a new ranging initiator
+    ///
+    /// A ping is sent every `ping_interval` ticks. In between, the initiator
+    /// listens for ranging requests. All messages are sent using `tx_config`
+    /// and received using `rx_config`.
+    ///
+    /// A ping is delayed while a frame is being received, but requests that
+    /// arrive while a ping is being sent are lost. `ping_interval` should
+    /// therefore be well above the responders' turnaround, the time from a
+    /// ping to the end of their request. Every ranging message is sent 10 ms
+    /// after it has been created, so that is at least 10 ms plus the time
+    /// the responder takes to react to the ping.
+    pub fn new(
+        dw1000: DW1000<SPI, Ready>,
+        ping_interval: u64,
+        tx_config: TxConfig,
+        rx_config: RxConfig,
+    ) -> Self {
+        RangingInitiator {
+            link: Link::new(dw1000, tx_config, rx_config),
+            ping_interval,
+            last_ping: None,
+            answering: None,
+        }
+    }
+
+    /// Advances the session
+    ///
+    /// `now` is the current value of the monotonic tick.
+    pub fn pump(&mut self, now: u64) -> Result<Option<RangingEvent>, Error<SPI>> {
+        let ping_due = match self.last_ping {
+            Some(last_ping) => now.wrapping_sub(last_ping) >= self.ping_interval,
+            None => true,
+        };
+        // Don't interrupt an ongoing transmission or reception for the ping
+        let ping_due = ping_due && self.link.is_interruptible()?;
+
+        match self.link.poll()? {
+            Status::Idle | Status::Busy if ping_due => {
+                self.link.send(Ping::new)?;
+                self.last_ping = Some(now);
+                Ok(None)
+            }
+            Status::Idle | Status::ReceiveFailed => {
+                self.link.receive()?;
+                Ok(None)
+            }
+            Status::Busy => Ok(None),
+            Status::Sent => {
+                self.link.receive()?;
+                Ok(self
+                    .answering
+                    .take()
+                    .map(|peer| RangingEvent::RequestAnswered { peer }))
+            }
+            Status::SendFailed => {
+                self.link.receive()?;
+                Ok(self.answering.take().map(|peer| RangingEvent::Failed {
+                    peer,
+                    reason: RangingFailure::Transmit,
+                }))
+            }
+            Status::Received(message, _) => {
+                let request = Request::decode::<SPI>(&message);
+                let source = message.frame.header.source;
+
+                match request {
+                    Ok(Some(request)) => {
+                        self.link.send(|dw1000| Response::new(dw1000, &request))?;
+                        self.answering = Some(request.source);
+                        Ok(None)
+                    }
+                    Ok(None) => {
+                        self.link.receive()?;
+                        Ok(None)
+                    }
+                    Err(_) => {
+                        self.link.receive()?;
+                        Ok(Some(RangingEvent::Failed {
+                            peer: source,
+                            reason: RangingFailure::Decode,
+                        }))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Ends the session and returns the DW1000
+    ///
+    /// Any ongoing operation is aborted. If that fails, the session is
+    /// returned along with the error.
+    #[allow(clippy::result_large_err)]
+    pub fn release(mut self) -> Result<DW1000<SPI, Ready>, (Self, Error<SPI>)> {
+        match self.link.ready() {
+            Ok(dw1000) => Ok(dw1000),
+            Err(error) => Err((self, error)),
+        }
+    }
+}
+
+/// Responds to pings and computes the distance to the initiator
+///
+/// See [module documentation] for more info.
+///
+/// [module documentation]: index.html
+pub struct RangingResponder<SPI> {
+    link: Link<SPI>,
+    response_timeout: u64,
+    awaiting: Option<(Option<mac::Address>, u64)>,
+}
+
+impl<SPI> RangingResponder<SPI>
+where
+    SPI: SpiDevice,
+{
+    /// Creates a new ranging responder
+    ///
+    /// If no ranging response arrives within `response_timeout` ticks after a
+    /// ranging request was sent, the range measurement fails. All messages are
+    /// sent using `tx_config` and received using `rx_config`.
+    pub fn new(
+        dw1000: DW1000<SPI, Ready>,
+        response_timeout: u64,
+        tx_config: TxConfig,
+        rx_config: RxConfig,
+    ) -> Self {
+        RangingResponder {
+            link: Link::new(dw1000, tx_config, rx_config),
+            response_timeout,
+            awaiting: None,
+        }
+    }
+
+    /// Advances the session
+    ///
+    /// `now` is the current value of the monotonic tick.
+    pub fn pump(&mut self, now: u64) -> Result<Option<RangingEvent>, Error<SPI>> {
+        // Check the timeout first, so it is detected regardless of what the
+        // DW1000 is doing. Anything that happened meanwhile is handled by the
+        // next call.
+        if let Some((peer, request_time)) = self.awaiting {
+            if now.wrapping_sub(request_time) >= self.response_timeout {
+                self.awaiting = None;
+                return Ok(Some(RangingEvent::Failed {
+                    peer,
+                    reason: RangingFailure::Timeout,
+                }));
+            }
+        }
+
+        match self.link.poll()? {
+            Status::Idle | Status::ReceiveFailed => {
+                self.link.receive()?;
+                Ok(None)
+            }
+            Status::Busy => Ok(None),
+            Status::Sent => {
+                self.link.receive()?;
+                Ok(None)
+            }
+            Status::SendFailed => {
+                self.link.receive()?;
+                Ok(self.awaiting.take().map(|(peer, _)| RangingEvent::Failed {
+                    peer,
+                    reason: RangingFailure::Transmit,
+                }))
+            }
+            Status::Received(message, rx_quality) => {
+                let ping = Ping::decode::<SPI>(&message);
+                let response = Response::decode::<SPI>(&message);
+                let source = message.frame.header.source;
+
+                match (ping, response) {
+                    (Ok(Some(ping)), _) => {
+                        // A new ping replaces any ongoing range measurement
+                        self.link.send(|dw1000| Request::new(dw1000, &ping))?;
+                        self.awaiting = Some((ping.source, now));
+                        Ok(None)
+                    }
+                    (_, Ok(Some(response))) => {
+                        self.link.receive()?;
+
+                        match self.awaiting {
+                            Some((peer, _)) if peer == response.source => {
+                                self.awaiting = None;
+                                let event = match compute_distance_mm(&response) {
+                                    Ok(distance_mm) => RangingEvent::Distance {
+                                        peer,
+                                        distance_mm,
+                                        rx_quality,
+                                    },
+                                    Err(error) => RangingEvent::Failed {
+                                        peer,
+                                        reason: RangingFailure::Distance(error),
+                                    },
+                                };
+                                Ok(Some(event))
+                            }
+                            // Not the response we're waiting for
+                            _ => Ok(None),
+                        }
+                    }
+                    (Err(_), _) | (_, Err(_)) => {
+                        self.link.receive()?;
+                        Ok(Some(RangingEvent::Failed {
+                            peer: source,
+                            reason: RangingFailure::Decode,
+                        }))
+                    }
+                    (Ok(None), Ok(None)) => {
+                        self.link.receive()?;
+                        Ok(None)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Ends the session and returns the DW1000
+    ///
+    /// Any ongoing operation is aborted. If that fails, the session is
+    /// returned along with the error.
+    #[allow(clippy::result_large_err)]
+    pub fn release(mut self) -> Result<DW1000<SPI, Ready>, (Self, Error<SPI>)> {
+        match self.link.ready() {
+            Ok(dw1000) => Ok(dw1000),
+            Err(error) => Err((self, error)),
+        }
+    }
+}
+
+/// The DW1000 in one of the states a ranging session uses
+enum Radio<SPI> {
+    Ready(DW1000<SPI, Ready>),
+    Sending(DW1000<SPI, Sending>),
+    Receiving(DW1000<SPI, SingleBufferReceiving>),
+    /// The DW1000 is changing states
+    ///
+    /// Every state transition puts the DW1000 back, even if it fails, so this
+    /// is never observed outside of those transitions.
+    Transition,
+}
+
+/// The result of polling the DW1000
+#[allow(clippy::large_enum_variant)]
+enum Status<'b> {
+    /// No operation is ongoing
+    Idle,
+    /// The ongoing operation hasn't finished yet
+    Busy,
+    /// A message has been sent
+    Sent,
+    /// A message could not be sent
+    SendFailed,
+    /// A message has been received
+    Received(hl::Message<'b>, Option<RxQuality>),
+    /// An error occured while receiving
+    ReceiveFailed,
+}
+
+/// The DW1000, its configuration and the receive buffer used by a ranging
+/// session
+struct Link<SPI> {
+    radio: Radio<SPI>,
+    tx_config: TxConfig,
+    rx_config: RxConfig,
+    buffer: [u8; 128],
+}
+
+impl<SPI> Link<SPI>
+where
+    SPI: SpiDevice,
+{
+    fn new(dw1000: DW1000<SPI, Ready>, tx_config: TxConfig, rx_config: RxConfig) -> Self {
+        Link {
+            radio: Radio::Ready(dw1000),
+            tx_config,
+            rx_config,
+            buffer: [0; 128],
+        }
+    }
+
+    /// Checks whether the ongoing operation can be aborted without losing a
+    /// message
+    ///
+    /// That is the case, if no operation is ongoing, or if the receiver hasn't
+    /// detected the preamble of a frame yet.
+    fn is_interruptible(&mut self) -> Result<bool, Error<SPI>> {
+        match &mut self.radio {
+            Radio::Ready(_) => Ok(true),
+            Radio::Sending(_) => Ok(false),
+            Radio::Receiving(dw1000) => Ok(dw1000.ll().sys_status().read()?.rxprd() == 0b0),
+            Radio::Transition => unreachable!(),
+        }
+    }
+
+    /// Checks whether the ongoing operation has finished
+    fn poll(&mut self) -> Result<Status<'_>, Error<SPI>> {
+        match &mut self.radio {
+            Radio::Ready(_) => Ok(Status::Idle),
+            Radio::Sending(dw1000) => match dw1000.wait_transmit() {
+                Ok(_) => Ok(Status::Sent),
+                Err(nb::Error::WouldBlock) => Ok(Status::Busy),
+                Err(nb::Error::Other(Error::Spi(error))) => Err(Error::Spi(error)),
+                Err(nb::Error::Other(_)) => Ok(Status::SendFailed),
+            },
+            Radio::Receiving(dw1000) => match dw1000.wait_receive(&mut self.buffer) {
+                Ok(message) => {
+                    // The quality is an addition to the message, so a frame
+                    // for which it can't be calculated is still used.
+                    let rx_quality = match dw1000.read_rx_quality() {
+                        Ok(rx_quality) => Some(rx_quality),
+                        Err(Error::BadRssiCalculation) => None,
+                        Err(error) => return Err(error),
+                    };
+                    Ok(Status::Received(message, rx_quality))
+                }
+                Err(nb::Error::WouldBlock) => Ok(Status::Busy),
+                Err(nb::Error::Other(Error::Spi(error))) => Err(Error::Spi(error)),
+                Err(nb::Error::Other(_)) => Ok(Status::ReceiveFailed),
+            },
+            Radio::Transition => unreachable!(),
+        }
+    }
+
+    /// Finishes any ongoing operation
+    fn ready(&mut self) -> Result<DW1000<SPI, Ready>, Error<SPI>> {
+        match mem::replace(&mut self.radio, Radio::Transition) {
+            Radio::Ready(dw1000) => Ok(dw1000),
+            Radio::Sending(dw1000) => dw1000.finish_sending().map_err(|(dw1000, error)| {
+                self.radio = Radio::Sending(dw1000);
+                error
+            }),
+            Radio::Receiving(dw1000) => dw1000.finish_receiving().map_err(|(dw1000, error)| {
+                self.radio = Radio::Receiving(dw1000);
+                error
+            }),
+            Radio::Transition => unreachable!(),
+        }
+    }
+
+    /// (Re-)starts receiving
+    fn receive(&mut self) -> Result<(), Error<SPI>> {
+        let mut dw1000 = self.ready()?;
+
+        // Reset the progress of earlier receive operations, so
+        // `is_interruptible` only looks at this one.
+        let result = dw1000.ll().sys_status().write(|w| {
+            w.rxprd(0b1) // Receiver Preamble Detected
+                .rxsfdd(0b1) // Receiver SFD Detected
+                .rxphd(0b1) // Receiver PHY Header Detected
+        });
+        if let Err(error) = result {
+            self.radio = Radio::Ready(dw1000);
+            return Err(error.into());
+        }
+
+        match dw1000.try_receive(self.rx_config) {
+            Ok(dw1000) => {
+                self.radio = Radio::Receiving(dw1000);
+                Ok(())
+            }
+            Err((dw1000, error)) => {
+                self.radio = Radio::Ready(dw1000);
+                Err(error)
+            }
+        }
+    }
+
+    /// Creates a message using `create` and sends it
+    fn send<T>(
+        &mut self,
+        create: impl FnOnce(&mut DW1000<SPI, Ready>) -> Result<TxMessage<T>, Error<SPI>>,
+    ) -> Result<(), Error<SPI>>
+    where
+        T: super::Message,
+    {
+        let mut dw1000 = self.ready()?;
+        let message = match create(&mut dw1000) {
+            Ok(message) => message,
+            Err(error) => {
+                self.radio = Radio::Ready(dw1000);
+                return Err(error);
+            }
+        };
+        match message.try_send(dw1000, self.tx_config) {
+            Ok(dw1000) => {
+                self.radio = Radio::Sending(dw1000);
+                Ok(())
+            }
+            Err((dw1000, error)) => {
+                self.radio = Radio::Ready(dw1000);
+                Err(error)
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! Helpers for time difference of arrival (TDoA) positioning
+//!
+//! In a TDoA system, tags only send out short blink frames ([`Blink`]). The
+//! anchors that receive a blink record its arrival time. Since every anchor
+//! has its own clock, the arrival times need to be converted into a common
+//! timebase, before their differences can be used to compute the position of
+//! the tag.
+//!
+//! To relate the clocks, a reference anchor regularly sends out sync beacons
+//! ([`SyncBeacon`]). Every other anchor feeds the beacons it receives into a
+//! [`ClockModel`], which can then convert the arrival times of blinks into the
+//! timebase of the reference anchor.
+//!
+//! Blinks are not regular IEEE 802.15.4 MAC frames. To receive them, frame
+//! filtering needs to be disabled, or [`FrameFilter::allow_frame_type_5`] needs
+//! to be set. Use [`DW1000::wait_receive_raw`] to receive them.
+//!
+//! [`FrameFilter::allow_frame_type_5`]: crate::configs::FrameFilter::allow_frame_type_5
+//! [`DW1000::wait_receive_raw`]: crate::DW1000::wait_receive_raw
+
+use embedded_hal::spi::SpiDevice;
+use serde::{Deserialize, Serialize};
+
+use super::{Message, Prelude, RxMessage, TxMessage, TX_DELAY};
+use crate::{
+    hl::SendTime,
+    mac,
+    math::round,
+    time::{Duration, Instant, TIME_MAX},
+    Error, Ready, Sending, TxConfig, DW1000,
+};
+
+/// A blink frame, as sent by TDoA tags
+///
+/// This is the minimal blink frame described in the DW1000 user manual. It
+/// consists of a single frame control octet, a sequence number and the 64-bit
+/// ID of the tag.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Blink {
+    /// The sequence number of the blink
+    pub seq: u8,
+
+    /// The ID of the tag that sent the blink
+    pub tag_id: u64,
+}
+
+impl Blink {
+    /// The frame control octet of a blink frame
+    pub const FRAME_CONTROL: u8 = 0xC5;
+
+    /// The length of an encoded blink frame, without the CRC
+    pub const LEN: usize = 10;
+
+    /// Writes the blink frame into `buffer` and returns its length
+    ///
+    /// Panics, if `buffer` is shorter than [`Blink::LEN`].
+    pub fn encode(&self, buffer: &mut [u8]) -> usize {
+        buffer[0] = Self::FRAME_CONTROL;
+        buffer[1] = self.seq;
+        buffer[2..Self::LEN].copy_from_slice(&self.tag_id.to_le_bytes());
+
+        Self::LEN
+    }
+
+    /// Decodes a received frame
+    ///
+    /// Returns `None`, if the frame is not a blink frame. A trailing CRC is
+    /// ignored.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::LEN || bytes[0] != Self::FRAME_CONTROL {
+            return None;
+        }
+
+        let mut tag_id = [0; 8];
+        tag_id.copy_from_slice(&bytes[2..Self::LEN]);
+
+        Some(Blink {
+            seq: bytes[1],
+            tag_id: u64::from_le_bytes(tag_id),
+        })
+    }
+
+    /// Sends the blink frame via the DW1000
+    ///
+    /// Uses [`DW1000::send_raw`] internally to send it.
+    pub fn send<SPI>(
+        &self,
+        dw1000: DW1000<SPI, Ready>,
+        send_time: SendTime,
+        config: TxConfig,
+    ) -> Result<DW1000<SPI, Sending>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        dw1000.send_raw(|buffer| self.encode(buffer), send_time, config)
+    }
+}
+
+/// Sync beacon message
+///
+/// This message is regularly broadcast by the reference anchor, to allow the
+/// other anchors to relate their clocks to its clock. See [module
+/// documentation] for more info.
+///
+/// [module documentation]: index.html
+#[derive(Debug, Deserialize, Serialize)]
+#[repr(C)]
+pub struct SyncBeacon {
+    /// When the beacon was sent, in local sender time
+    pub beacon_tx_time: Instant,
+}
+
+impl SyncBeacon {
+    /// Creates a new sync beacon message
+    ///
+    /// Only creates the message, but doesn't yet send it. Sets the transmission
+    /// time to 10 milliseconds in the future. Make sure to send the message
+    /// within that time frame, or the clock models will be negatively affected.
+    pub fn new<SPI>(dw1000: &mut DW1000<SPI, Ready>) -> Result<TxMessage<Self>, Error<SPI>>
+    where
+        SPI: SpiDevice,
+    {
+        let tx_time = dw1000.sys_time()? + Duration::from_nanos(TX_DELAY);
+        let beacon_tx_time = tx_time + dw1000.get_tx_antenna_delay()?;
+
+        Ok(TxMessage {
+            recipient: mac::Address::broadcast(&mac::AddressMode::Short),
+            tx_time,
+            payload: SyncBeacon { beacon_tx_time },
+        })
+    }
+}
+
+impl Message for SyncBeacon {
+    const PRELUDE: Prelude = Prelude(b"RANGING SYNC BEACON");
+    const PRELUDE_LEN: usize = 19;
+}
+
+/// A blink, with its arrival time in the timebase of the reference anchor
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct BlinkArrival {
+    /// The blink that arrived
+    pub blink: Blink,
+
+    /// When the blink arrived, in the timebase of the reference anchor
+    pub arrival_time: Instant,
+}
+
+/// Models the clock of an anchor relative to the clock of the reference anchor
+///
+/// The model estimates the offset and drift of the local clock, using a linear
+/// regression over the last `N` sync beacons. The 40-bit wrap of the DW1000
+/// timestamps is handled transparently.
+///
+/// Samples that are more than half a wrap period (about 8.6 s) older than the
+/// newest one are ignored, so make sure that the `N` beacons span less time
+/// than that.
+#[derive(Debug)]
+pub struct ClockModel<const N: usize> {
+    samples: [(u64, u64); N],
+    len: usize,
+    next: usize,
+    fit: Option<Fit>,
+}
+
+/// The result of the linear regression
+#[derive(Clone, Copy, Debug)]
+struct Fit {
+    /// The newest local time
+    local: Instant,
+    /// The reference time that corresponds to `local`, as measured
+    reference: Instant,
+    /// The estimated offset at `local`, in DW1000 time units
+    offset: f64,
+    /// The estimated drift, relative to the reference clock
+    drift: f64,
+}
+
+impl<const N: usize> ClockModel<N> {
+    /// Creates a new clock model without any samples
+    pub const fn new() -> Self {
+        ClockModel {
+            samples: [(0, 0); N],
+            len: 0,
+            next: 0,
+            fit: None,
+        }
+    }
+
+    /// Adds a received sync beacon
+    ///
+    /// `propagation_delay` is the time of flight between the reference anchor
+    /// and this anchor, which is known from their positions.
+    pub fn add_beacon(&mut self, beacon: &RxMessage<SyncBeacon>, propagation_delay: Duration) {
+        self.add_sample(
+            beacon.rx_time,
+            beacon.payload.beacon_tx_time + propagation_delay,
+        );
+    }
+
+    /// Adds a pair of corresponding local and reference times
+    ///
+    /// Samples must be added in chronological order.
+    pub fn add_sample(&mut self, local: Instant, reference: Instant) {
+        if N == 0 {
+            return;
+        }
+
+        self.samples[self.next] = (local.value(), reference.value());
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+
+        self.fit = self.compute_fit(local, reference);
+    }
+
+    /// Removes all samples
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+        self.fit = None;
+    }
+
+    /// Indicates whether enough samples have been added to convert times
+    pub fn is_synchronized(&self) -> bool {
+        self.fit.is_some()
+    }
+
+    /// Returns the estimated drift of the local clock in ppm
+    ///
+    /// A positive value means the local clock is running faster than the
+    /// reference clock. Returns `None`, if not enough samples have been added.
+    pub fn drift_ppm(&self) -> Option<f64> {
+        // `drift` is the rate of the reference clock relative to ours
+        self.fit.map(|fit| -fit.drift * 1e6)
+    }
+
+    /// Converts a local time into the timebase of the reference anchor
+    ///
+    /// Returns `None`, if not enough samples have been added.
+    pub fn to_reference(&self, local: Instant) -> Option<Instant> {
+        let fit = self.fit?;
+
+        let dx = wrapping_diff(local.value(), fit.local.value());
+        let correction = round(fit.offset + fit.drift * dx as f64);
+
+        let reference = fit.reference.value() as i64 + dx + correction;
+        let reference = reference.rem_euclid(TIME_MAX as i64 + 1) as u64;
+
+        Instant::new(reference)
+    }
+
+    /// Converts the arrival time of a blink into the timebase of the reference
+    /// anchor
+    ///
+    /// Returns `None`, if not enough samples have been added.
+    pub fn correct_blink(&self, blink: Blink, rx_time: Instant) -> Option<BlinkArrival> {
+        Some(BlinkArrival {
+            blink,
+            arrival_time: self.to_reference(rx_time)?,
+        })
+    }
+
+    fn compute_fit(&self, local: Instant, reference: Instant) -> Option<Fit> {
+        // To keep the numbers small, everything is relative to the newest
+        // sample. `x` is the local time, `y` is the difference between the
+        // reference and local time.
+        let mut count = 0.0;
+        let mut sum_x = 0.0;
+        let mut sum_y = 0.0;
+        let mut points = [(0.0, 0.0); N];
+        for &(sample_local, sample_reference) in &self.samples[..self.len] {
+            let x = wrapping_diff(sample_local, local.value());
+            if x > 0 {
+                // More than half a wrap period old
+                continue;
+            }
+            let y = wrapping_diff(sample_reference, reference.value()) - x;
+
+            points[count as usize] = (x as f64, y as f64);
+            count += 1.0;
+            sum_x += x as f64;
+            sum_y += y as f64;
+        }
+
+        if count < 2.0 {
+            return None;
+        }
+
+        let mean_x = sum_x / count;
+        let mean_y = sum_y / count;
+
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for &(x, y) in &points[..count as usize] {
+            covariance += (x - mean_x) * (y - mean_y);
+            variance += (x - mean_x) * (x - mean_x);
+        }
+
+        let drift = if variance > 0.0 {
+            covariance / variance
+        } else {
+            0.0
+        };
+
+        Some(Fit {
+            local,
+            reference,
+            offset: mean_y - drift * mean_x,
+            drift,
+        })
+    }
+}
+
+impl<const N: usize> Default for ClockModel<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Computes `a - b` for two 40-bit timestamps
+///
+/// The result is in the range of -2^39 to 2^39 - 1.
+fn wrapping_diff(a: u64, b: u64) -> i64 {
+    const WRAP: i64 = TIME_MAX as i64 + 1;
+
+    let diff = (a.wrapping_sub(b) & TIME_MAX) as i64;
+    if diff >= WRAP / 2 {
+        diff - WRAP
+    } else {
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instant(value: u64) -> Instant {
+        Instant::new(value & TIME_MAX).unwrap()
+    }
+
+    #[test]
+    fn blink_roundtrip() {
+        let blink = Blink {
+            seq: 42,
+            tag_id: 0x0123_4567_89ab_cdef,
+        };
+
+        let mut buffer = [0; 12];
+        let len = blink.encode(&mut buffer);
+
+        assert_eq!(len, Blink::LEN);
+        assert_eq!(buffer[0], 0xC5);
+        assert_eq!(Blink::decode(&buffer), Some(blink));
+        assert_eq!(Blink::decode(&buffer[..len - 1]), None);
+    }
+
+    #[test]
+    fn clock_model_needs_two_samples() {
+        let mut model = ClockModel::<4>::new();
+        assert_eq!(model.to_reference(instant(0)).map(|i| i.value()), None);
+
+        model.add_sample(instant(1000), instant(5000));
+        assert!(!model.is_synchronized());
+
+        model.add_sample(instant(2000), instant(6000));
+        assert!(model.is_synchronized());
+        assert_eq!(model.to_reference(instant(3000)).unwrap().value(), 7000);
+    }
+
+    #[test]
+    fn clock_model_estimates_drift_across_wrap() {
+        // The local clock runs 20 ppm fast and wraps around during the test
+        let interval = 6_400_000_000; // 100 ms
+        let start_local = TIME_MAX - 3 * interval;
+        let start_reference = 1_000_000;
+
+        let mut model = ClockModel::<8>::new();
+        for i in 0..8 {
+            let reference = start_reference + i * interval;
+            let local = start_local + i * interval + i * interval / 50_000;
+            model.add_sample(instant(local), instant(reference));
+        }
+
+        let drift_ppm = model.drift_ppm().unwrap();
+        assert!((drift_ppm - 20.0).abs() < 0.01, "{}", drift_ppm);
+
+        let local = start_local + 10 * interval + 10 * interval / 50_000;
+        let reference = model.to_reference(instant(local)).unwrap();
+        let expected = start_reference + 10 * interval;
+        assert!(wrapping_diff(reference.value(), expected).abs() <= 2);
+    }
+
+    #[test]
+    fn clock_model_ignores_stale_samples() {
+        let mut model = ClockModel::<4>::new();
+        model.add_sample(instant(0), instant(0));
+        model.add_sample(instant(1000), instant(1000));
+
+        // More than half a wrap period later
+        let later = (1 << 39) + 2000;
+        model.add_sample(instant(later), instant(later + 500));
+        assert!(!model.is_synchronized());
+
+        model.add_sample(instant(later + 1000), instant(later + 1500));
+        assert_eq!(
+            model.to_reference(instant(later + 2000)).unwrap().value(),
+            later + 2500
+        );
+    }
+}
@@ -146,14 +146,21 @@ fn main() -> ! {
         /*
         3. Wait for response
         */
+        let rx_config = RxConfig::default();
         let mut receiving = dw1000
-            .receive(RxConfig::default())
+            .receive(rx_config)
             .expect("Failed to receive message");
 
         // Set timer for timeout
         timer.start(5_000_000u32);
         let result = block_timeout!(&mut timer, receiving.wait_receive(&mut buffer2));
 
+        // The quality of the message can only be read before finishing
+        let rx_quality = match result {
+            Ok(_) => Some(receiving.read_rx_quality()),
+            Err(_) => None,
+        };
+
         dw1000 = receiving
             .finish_receiving()
             .expect("Failed to finish receiving");
@@ -205,8 +212,16 @@ fn main() -> ! {
             _ => continue,
         };
 
+        let rx_quality = match rx_quality {
+            Some(Ok(rx_quality)) => rx_quality,
+            _ => {
+                defmt::error!("Failed to read quality of ranging response");
+                continue;
+            }
+        };
+
         // Ranging response received. Compute distance.
-        match ranging::compute_distance_mm(&response) {
+        match ranging::compute_corrected_distance_mm(&response, &rx_quality, &rx_config) {
             Ok(distance_mm) => {
                 dwm1001.leds.D9.enable();
                 delay.delay_ms(10u32);
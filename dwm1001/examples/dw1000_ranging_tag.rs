@@ -78,8 +78,9 @@ fn main() -> ! {
 
     loop {
         defmt::info!("waiting for base station ping");
+        let rx_config = RxConfig::default();
         let mut receiving = dw1000
-            .receive(RxConfig::default())
+            .receive(rx_config)
             .expect("Failed to receive message");
 
         timeout_timer.start(500_000u32);
@@ -88,6 +89,12 @@ fn main() -> ! {
             receiving.wait_receive(&mut buf)
         });
 
+        // The quality of the message can only be read before finishing
+        let rx_quality = match message {
+            Ok(_) => Some(receiving.read_rx_quality()),
+            Err(_) => None,
+        };
+
         dw1000 = receiving
             .finish_receiving()
             .expect("Failed to finish receiving");
@@ -151,7 +158,21 @@ fn main() -> ! {
             };
 
             // Ranging response received. Compute distance.
-            let distance_mm = ranging::compute_distance_mm(&response).unwrap();
+            let rx_quality = match rx_quality {
+                Some(Ok(rx_quality)) => rx_quality,
+                _ => {
+                    defmt::error!("Failed to read quality of ranging response");
+                    continue;
+                }
+            };
+            let distance_mm =
+                match ranging::compute_corrected_distance_mm(&response, &rx_quality, &rx_config) {
+                    Ok(distance_mm) => distance_mm,
+                    Err(e) => {
+                        defmt::error!("Ranging response error: {:?}", defmt::Debug2Format(&e));
+                        continue;
+                    }
+                };
 
             dwm1001.leds.D9.enable();
             delay.delay_ms(10u32);